@@ -0,0 +1,401 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use inquire::MultiSelect;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::config::ArxivConfig;
+use crate::dedupe::hash_file;
+use crate::download::Downloader;
+use crate::library::Library;
+
+/// How many ids to ask the arXiv API about per request.
+const BATCH_SIZE: usize = 50;
+
+static ARXIV_REF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)arxiv(?:\.org/(?:abs|pdf)/|:\s*)(\d{4}\.\d{4,5}|[a-z-]+(?:\.[a-z]{2})?/\d{7})(?:v(\d+))?",
+    )
+    .unwrap()
+});
+/// Entry ids in an API response look like `http://arxiv.org/abs/1706.03762v7`.
+static ENTRY_ID: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<id>\s*https?://arxiv\.org/abs/(\S+?)v(\d+)\s*</id>").unwrap());
+
+/// An arXiv identifier, with the version if one was given.
+pub struct ArxivRef {
+    pub id: String,
+    pub version: Option<u32>,
+}
+
+/// Old-style ids are written with a lowercase archive and an uppercase
+/// subject class, as in `math.AG/0601001`.
+pub(crate) fn canonical_id(id: &str) -> String {
+    match id.split_once('/') {
+        Some((archive, number)) => match archive.split_once('.') {
+            Some((archive, class)) => format!(
+                "{}.{}/{}",
+                archive.to_lowercase(),
+                class.to_uppercase(),
+                number
+            ),
+            None => format!("{}/{}", archive.to_lowercase(), number),
+        },
+        None => id.to_string(),
+    }
+}
+
+/// The id the API uses for a paper. Old-style ids lose their subject class,
+/// so `math.AG/0601001` becomes `math/0601001`.
+fn api_id(id: &str) -> String {
+    match id.split_once('/') {
+        Some((archive, number)) => {
+            let archive = archive.split('.').next().unwrap_or(archive);
+            format!("{}/{}", archive.to_lowercase(), number)
+        }
+        None => id.to_string(),
+    }
+}
+
+/// The first arXiv identifier mentioned in any of `texts`.
+pub fn find_arxiv_ref(texts: &[&str]) -> Option<ArxivRef> {
+    texts
+        .iter()
+        .find_map(|text| ARXIV_REF.captures(text))
+        .map(|c| ArxivRef {
+            id: canonical_id(&c[1]),
+            version: c.get(2).and_then(|v| v.as_str().parse().ok()),
+        })
+}
+
+/// Latest version of every id in `ids` that the API knows about, keyed by
+/// the ids as given.
+async fn get_latest_versions(
+    downloader: &Downloader,
+    config: &ArxivConfig,
+    ids: &[String],
+) -> Result<HashMap<String, u32>> {
+    let mut by_api_id = HashMap::new();
+    for batch in ids.chunks(BATCH_SIZE) {
+        let url = format!(
+            "{}?id_list={}&max_results={}",
+            config.api_url,
+            batch
+                .iter()
+                .map(|id| api_id(id))
+                .collect::<Vec<_>>()
+                .join(","),
+            batch.len()
+        );
+        let feed = downloader
+            .get_text(&url)
+            .await
+            .context("Error querying the arXiv API.")?;
+        for entry in ENTRY_ID.captures_iter(&feed) {
+            if let Ok(version) = entry[2].parse::<u32>() {
+                by_api_id.insert(api_id(&entry[1]), version);
+            }
+        }
+    }
+    Ok(ids
+        .iter()
+        .filter_map(|id| Some((id.clone(), *by_api_id.get(&api_id(id))?)))
+        .collect())
+}
+
+struct OutdatedPaper {
+    id: u32,
+    base_path: PathBuf,
+    url: String,
+    arxiv_id: String,
+    current: Option<u32>,
+    latest: u32,
+}
+
+impl fmt::Display for OutdatedPaper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let current = self
+            .current
+            .map_or("unknown version".to_string(), |v| format!("v{}", v));
+        write!(
+            f,
+            "{} (arXiv {}): {} -> v{}",
+            self.base_path.display(),
+            self.arxiv_id,
+            current,
+            self.latest
+        )
+    }
+}
+
+async fn find_outdated(
+    conn: &libsql::Connection,
+    library: &Library,
+    downloader: &Downloader,
+    config: &ArxivConfig,
+) -> Result<Vec<OutdatedPaper>> {
+    let mut rows = conn
+        .query(
+            "SELECT id, canonical_base_path, url, citation, arxiv_id, arxiv_version FROM papers
+             WHERE deleted_at IS NULL",
+            (),
+        )
+        .await?;
+
+    let mut papers = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let url: String = row.get(2)?;
+        let citation: String = row.get(3)?;
+        let stored_id: Option<String> = row.get(4)?;
+        let stored_version: Option<u32> = row.get(5)?;
+
+        // Papers added before ids were recorded only mention them in the URL or citation
+        let found = find_arxiv_ref(&[&url, &citation]);
+        let stored_id = stored_id.map(|id| canonical_id(&id));
+        let Some(arxiv_id) = stored_id.or_else(|| found.as_ref().map(|f| f.id.clone())) else {
+            continue;
+        };
+        let current = stored_version.or_else(|| found.and_then(|f| f.version));
+        papers.push(OutdatedPaper {
+            id: row.get(0)?,
+            base_path: library.resolve(&row.get::<String>(1)?),
+            url,
+            arxiv_id,
+            current,
+            latest: 0,
+        });
+    }
+
+    if papers.is_empty() {
+        return Ok(papers);
+    }
+
+    println!(
+        "Checking {} arXiv paper(s) for new versions...",
+        papers.len()
+    );
+    let ids = papers
+        .iter()
+        .map(|p| p.arxiv_id.clone())
+        .collect::<Vec<_>>();
+    let latest = get_latest_versions(downloader, config, &ids).await?;
+
+    Ok(papers
+        .into_iter()
+        .filter_map(|mut paper| {
+            paper.latest = *latest.get(&paper.arxiv_id)?;
+            paper
+                .current
+                .is_none_or(|v| v < paper.latest)
+                .then_some(paper)
+        })
+        .collect())
+}
+
+/// Where the current PDF is kept once a newer version replaces it.
+fn archive_path(base_path: &Path, version: Option<u32>) -> PathBuf {
+    let name = match version {
+        Some(v) => format!("paper.v{}.pdf", v),
+        None => format!("paper.{}.pdf", Local::now().format("%Y%m%d%H%M%S")),
+    };
+    base_path.join("versions").join(name)
+}
+
+/// Download the latest version of a paper and move the current PDF into
+/// `versions/` next to the notes.
+async fn update_paper(
+    conn: &libsql::Connection,
+    downloader: &Downloader,
+    config: &ArxivConfig,
+    paper: &OutdatedPaper,
+) -> Result<()> {
+    let pdf_url = format!(
+        "{}/{}v{}",
+        config.pdf_url.trim_end_matches('/'),
+        api_id(&paper.arxiv_id),
+        paper.latest
+    );
+    let download = downloader.download_pdf(&pdf_url).await?;
+
+    let pdf_path = paper.base_path.join("paper.pdf");
+    let new_pdf_path = paper.base_path.join("paper.pdf.papr-new");
+    let archive = archive_path(&paper.base_path, paper.current);
+    fs::create_dir_all(archive.parent().unwrap()).context("Error creating versions directory.")?;
+    fs::copy(&download, &new_pdf_path).context("Error copying PDF.")?;
+
+    let res = async {
+        // Point an arXiv URL at the version now in the library
+        let url = match find_arxiv_ref(&[&paper.url]) {
+            Some(found) if found.id == paper.arxiv_id => pdf_url.clone(),
+            _ => paper.url.clone(),
+        };
+
+        let tx = conn.transaction().await?;
+        tx.execute(
+            "UPDATE papers SET url = ?1, arxiv_id = ?2, arxiv_version = ?3, pdf_hash = ?4
+             WHERE id = ?5",
+            (
+                url,
+                paper.arxiv_id.clone(),
+                paper.latest,
+                hash_file(&new_pdf_path)?,
+                paper.id,
+            ),
+        )
+        .await
+        .context("Error updating papers table.")?;
+
+        let archived = pdf_path.exists();
+        if archived {
+            fs::rename(&pdf_path, &archive).context("Error archiving old PDF.")?;
+        }
+
+        let swapped = fs::rename(&new_pdf_path, &pdf_path).context("Error replacing PDF.");
+        let committed = match swapped {
+            Ok(()) => tx
+                .commit()
+                .await
+                .context("Error committing paper to the database."),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = committed {
+            let _ = fs::remove_file(&pdf_path);
+            if archived {
+                let _ = fs::rename(&archive, &pdf_path);
+            }
+            return Err(e);
+        }
+        if archived {
+            println!("Previous version kept at {}", archive.display());
+        }
+        Ok(())
+    }
+    .await;
+
+    if new_pdf_path.exists() {
+        let _ = fs::remove_file(&new_pdf_path);
+    }
+    res
+}
+
+pub async fn handle_outdated(
+    conn: &libsql::Connection,
+    library: &Library,
+    downloader: &Downloader,
+    config: &ArxivConfig,
+) -> Result<()> {
+    let outdated = find_outdated(conn, library, downloader, config).await?;
+    if outdated.is_empty() {
+        println!("All arXiv papers are up to date.");
+        return Ok(());
+    }
+
+    println!("Newer versions are available:");
+    for paper in &outdated {
+        println!("  {}", paper);
+    }
+
+    let Some(selections) = MultiSelect::new(
+        "Select papers to update (Space to toggle, Enter to confirm, Esc to skip):",
+        outdated,
+    )
+    .prompt_skippable()?
+    else {
+        return Ok(());
+    };
+
+    for paper in &selections {
+        update_paper(conn, downloader, config, paper).await?;
+        println!(
+            "Updated {} to v{}.",
+            paper.base_path.display(),
+            paper.latest
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::tests::{downloader, reply, serve};
+
+    #[test]
+    fn finds_new_style_id_and_version() {
+        let found = find_arxiv_ref(&["", "https://arxiv.org/abs/1706.03762v7"]).unwrap();
+        assert_eq!(found.id, "1706.03762");
+        assert_eq!(found.version, Some(7));
+
+        let found = find_arxiv_ref(&["See arXiv: 2101.00001 for details"]).unwrap();
+        assert_eq!(found.id, "2101.00001");
+        assert_eq!(found.version, None);
+    }
+
+    #[test]
+    fn finds_old_style_id() {
+        let found = find_arxiv_ref(&["https://arxiv.org/pdf/HEP-TH/9901001v2"]).unwrap();
+        assert_eq!(found.id, "hep-th/9901001");
+        assert_eq!(found.version, Some(2));
+
+        let found = find_arxiv_ref(&["arXiv:math.AG/0601001"]).unwrap();
+        assert_eq!(found.id, "math.AG/0601001");
+        assert_eq!(canonical_id("math.ag/0601001"), "math.AG/0601001");
+        assert_eq!(api_id("math.AG/0601001"), "math/0601001");
+    }
+
+    #[test]
+    fn ignores_text_without_arxiv_id() {
+        assert!(find_arxiv_ref(&["https://doi.org/10.1000/182", "1706.03762"]).is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_latest_versions_from_api() {
+        let feed = b"<feed>
+            <entry><id>http://arxiv.org/abs/1706.03762v7</id></entry>
+            <entry>
+              <id>
+                https://arxiv.org/abs/hep-th/9901001v3
+              </id>
+            </entry>
+        </feed>";
+        let (url, requests) = serve(vec![reply("200 OK", feed)]).await;
+        let config = ArxivConfig {
+            api_url: url,
+            ..Default::default()
+        };
+        let ids = ["1706.03762".to_string(), "hep-th/9901001".to_string()];
+
+        let latest = get_latest_versions(&downloader(), &config, &ids)
+            .await
+            .unwrap();
+        assert_eq!(latest.get("1706.03762"), Some(&7));
+        assert_eq!(latest.get("hep-th/9901001"), Some(&3));
+        assert!(
+            requests.lock().unwrap()[0]
+                .contains("?id_list=1706.03762,hep-th/9901001&max_results=2")
+        );
+    }
+
+    #[tokio::test]
+    async fn matches_old_style_ids_with_subject_class() {
+        let feed = b"<feed><entry><id>http://arxiv.org/abs/math/0601001v2</id></entry></feed>";
+        let (url, requests) = serve(vec![reply("200 OK", feed)]).await;
+        let config = ArxivConfig {
+            api_url: url,
+            ..Default::default()
+        };
+        let ids = ["math.AG/0601001".to_string()];
+
+        let latest = get_latest_versions(&downloader(), &config, &ids)
+            .await
+            .unwrap();
+        assert_eq!(latest.get("math.AG/0601001"), Some(&2));
+        assert!(requests.lock().unwrap()[0].contains("?id_list=math/0601001&"));
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use clap::ValueEnum;
+use inquire::Select;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::download::Downloader;
+use crate::library::Library;
+use crate::search;
+
+/// Directory inside a paper directory that holds its attachments.
+pub(crate) const ATTACHMENTS_DIR: &str = "attachments";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AttachmentKind {
+    Supplementary,
+    Slides,
+    Poster,
+    Code,
+    Other,
+}
+
+impl AttachmentKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Supplementary => "supplementary",
+            Self::Slides => "slides",
+            Self::Poster => "poster",
+            Self::Code => "code",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for AttachmentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A PDF attached to a paper, for searching alongside `paper.pdf`.
+pub struct AttachmentPdf {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+pub async fn get_attachment_pdfs(
+    conn: &libsql::Connection,
+    paper_id: u32,
+    base_path: &Path,
+) -> Result<Vec<AttachmentPdf>> {
+    let mut rows = conn
+        .query(
+            "SELECT kind, file_name FROM attachments WHERE paper_id = ?1 ORDER BY id",
+            [paper_id],
+        )
+        .await?;
+
+    let mut res = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let kind: String = row.get(0)?;
+        let file_name: String = row.get(1)?;
+        let path = base_path.join(ATTACHMENTS_DIR).join(&file_name);
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+        {
+            res.push(AttachmentPdf {
+                label: format!("{}: {}", kind, file_name),
+                path,
+            });
+        }
+    }
+
+    Ok(res)
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Pick a file name in `dir` based on `name` that does not exist yet.
+pub(crate) fn free_file_name(dir: &Path, name: &str) -> String {
+    if !dir.join(name).exists() {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+        _ => (name, String::new()),
+    };
+    (2..)
+        .map(|i| format!("{}_{}{}", stem, i, ext))
+        .find(|candidate| !dir.join(candidate).exists())
+        .unwrap()
+}
+
+/// Copy `file` into the attachments directory of a paper under a free name
+/// based on `name` and record it. The copy is removed if recording fails.
+async fn store_attachment(
+    conn: &libsql::Connection,
+    paper_id: u32,
+    base_path: &Path,
+    file: &Path,
+    name: &str,
+    source: &str,
+    kind: AttachmentKind,
+) -> Result<PathBuf> {
+    let attachments_dir = base_path.join(ATTACHMENTS_DIR);
+    fs::create_dir_all(&attachments_dir).context("Error creating attachments directory.")?;
+    let file_name = free_file_name(&attachments_dir, name);
+    let file_path = attachments_dir.join(&file_name);
+    fs::copy(file, &file_path).with_context(|| format!("Error copying {:?}.", file))?;
+
+    let res = conn
+        .execute(
+            "INSERT INTO attachments (paper_id, kind, file_name, source, date_added)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            (
+                paper_id,
+                kind.as_str(),
+                file_name,
+                source,
+                Local::now().format("%Y-%m-%d").to_string(),
+            ),
+        )
+        .await;
+    if let Err(e) = res {
+        let _ = fs::remove_file(&file_path);
+        return Err(e).context("Error updating attachments table.");
+    }
+    Ok(file_path)
+}
+
+pub async fn handle_attach(
+    conn: &libsql::Connection,
+    library: &Library,
+    downloader: &Downloader,
+    query: String,
+    source: String,
+    kind: AttachmentKind,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper to attach to:", matching_papers)
+        .prompt()
+        .context("No paper selected.")?;
+
+    // Name the file after the last URL segment or the local file name
+    let (file_source, download, original_name) = if is_url(&source) {
+        let name = source
+            .split(['?', '#'])
+            .next()
+            .and_then(|s| s.trim_end_matches('/').rsplit('/').next())
+            .filter(|s| !s.is_empty() && !s.contains(':'))
+            .map(str::to_string);
+        let download = downloader.download(&source).await?;
+        (download.path().to_path_buf(), Some(download), name)
+    } else {
+        let path = PathBuf::from(&source);
+        let name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .map(str::to_string);
+        (path, None, name)
+    };
+    let original_name = original_name.unwrap_or_else(|| kind.to_string());
+
+    let base_path = Path::new(&paper_selection.canonical_base_path);
+    let file_path = store_attachment(
+        conn,
+        paper_selection.id,
+        base_path,
+        &file_source,
+        &original_name,
+        &source,
+        kind,
+    )
+    .await?;
+    drop(download);
+
+    println!("Attached {} as {}.", file_path.display(), kind);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::memory_db;
+
+    #[test]
+    fn numbers_taken_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(free_file_name(dir.path(), "slides.pdf"), "slides.pdf");
+        fs::write(dir.path().join("slides.pdf"), "").unwrap();
+        fs::write(dir.path().join("slides_2.pdf"), "").unwrap();
+        assert_eq!(free_file_name(dir.path(), "slides.pdf"), "slides_3.pdf");
+        fs::write(dir.path().join(".env"), "").unwrap();
+        assert_eq!(free_file_name(dir.path(), ".env"), ".env_2");
+        fs::write(dir.path().join("README"), "").unwrap();
+        assert_eq!(free_file_name(dir.path(), "README"), "README_2");
+    }
+
+    #[tokio::test]
+    async fn lists_only_pdf_attachments() {
+        let conn = memory_db().await;
+        conn.execute_batch(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation) VALUES
+                ('a', '', '2024-01-01', ''), ('b', '', '2024-01-01', '');
+             INSERT INTO attachments (paper_id, kind, file_name, source, date_added) VALUES
+                (1, 'code', 'code.zip', 'code.zip', '2024-01-01'),
+                (1, 'slides', 'talk.PDF', 'talk.PDF', '2024-01-01'),
+                (2, 'poster', 'poster.pdf', 'poster.pdf', '2024-01-01');",
+        )
+        .await
+        .unwrap();
+
+        let pdfs = get_attachment_pdfs(&conn, 1, Path::new("/lib/paper"))
+            .await
+            .unwrap();
+        assert_eq!(pdfs.len(), 1);
+        assert_eq!(pdfs[0].label, "slides: talk.PDF");
+        assert_eq!(pdfs[0].path, Path::new("/lib/paper/attachments/talk.PDF"));
+    }
+
+    #[tokio::test]
+    async fn stores_and_records_attachments() {
+        let conn = memory_db().await;
+        conn.execute(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+             VALUES ('paper', '', '2024-01-01', '')",
+            (),
+        )
+        .await
+        .unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let source = root.path().join("slides.pdf");
+        fs::write(&source, "%PDF").unwrap();
+        let paper = root.path().join("paper");
+
+        for expected in ["slides.pdf", "slides_2.pdf"] {
+            let path = store_attachment(
+                &conn,
+                1,
+                &paper,
+                &source,
+                "slides.pdf",
+                "slides.pdf",
+                AttachmentKind::Slides,
+            )
+            .await
+            .unwrap();
+            assert_eq!(path, paper.join("attachments").join(expected));
+            assert!(path.is_file());
+        }
+        let pdfs = get_attachment_pdfs(&conn, 1, &paper).await.unwrap();
+        let labels = pdfs.iter().map(|p| p.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["slides: slides.pdf", "slides: slides_2.pdf"]);
+        let mut rows = conn.query("SELECT COUNT(*) FROM papers", ()).await.unwrap();
+        assert_eq!(
+            rows.next().await.unwrap().unwrap().get::<u32>(0).unwrap(),
+            1
+        );
+
+        // A paper that is not in the database leaves no copy behind
+        let res = store_attachment(
+            &conn,
+            9,
+            &paper,
+            &source,
+            "slides.pdf",
+            "slides.pdf",
+            AttachmentKind::Slides,
+        )
+        .await;
+        assert!(res.is_err());
+        assert!(!paper.join("attachments/slides_3.pdf").exists());
+    }
+}
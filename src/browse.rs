@@ -0,0 +1,156 @@
+use anyhow::Result;
+use inquire::Select;
+use std::fmt;
+use std::path::Path;
+
+use crate::config::ViewerConfig;
+use crate::search::{PdfMatch, TypstMatch};
+use crate::viewer;
+
+/// A search result from either the PDF text or the notes of a paper.
+pub enum Hit {
+    Pdf(PdfMatch),
+    Note(TypstMatch),
+}
+
+impl Hit {
+    fn paper_id(&self) -> u32 {
+        match self {
+            Self::Pdf(m) => m.paper_id,
+            Self::Note(m) => m.paper_id,
+        }
+    }
+
+    fn canonical_path(&self) -> &str {
+        match self {
+            Self::Pdf(m) => &m.canonical_path,
+            Self::Note(m) => &m.canonical_path,
+        }
+    }
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = Path::new(self.canonical_path())
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Unknown");
+        match self {
+            Self::Pdf(m) => write!(f, "{} ({}, page {}): {}", name, m.source, m.page, m.excerpt),
+            Self::Note(m) => write!(f, "{} (notes, line {}): {}", name, m.line_number, m.excerpt),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    OpenPdf,
+    OpenNote,
+    EditCitation,
+    Retag,
+}
+
+/// An action offered for a particular hit, labelled with where it leads.
+struct Choice {
+    action: Action,
+    label: String,
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
+fn choices(hit: &Hit) -> Vec<Choice> {
+    let (open_pdf, open_note) = match hit {
+        Hit::Pdf(m) => (
+            format!("Open {} at page {}", m.source, m.page),
+            "Open notes".to_string(),
+        ),
+        Hit::Note(m) => (
+            "Open PDF".to_string(),
+            format!("Open notes at line {}", m.line_number),
+        ),
+    };
+    vec![
+        Choice {
+            action: Action::OpenPdf,
+            label: open_pdf,
+        },
+        Choice {
+            action: Action::OpenNote,
+            label: open_note,
+        },
+        Choice {
+            action: Action::EditCitation,
+            label: "Edit citation".to_string(),
+        },
+        Choice {
+            action: Action::Retag,
+            label: "Retag paper".to_string(),
+        },
+    ]
+}
+
+async fn run_action(
+    conn: &libsql::Connection,
+    viewer_config: &ViewerConfig,
+    hit: &Hit,
+    action: Action,
+) -> Result<()> {
+    let base_path = Path::new(hit.canonical_path());
+    match (action, hit) {
+        (Action::OpenPdf, Hit::Pdf(m)) => viewer::open_pdf(viewer_config, &m.path, Some(m.page)),
+        (Action::OpenPdf, Hit::Note(_)) => {
+            let pdf_path = base_path.join("paper.pdf");
+            if !pdf_path.exists() {
+                anyhow::bail!("{} does not exist.", pdf_path.display());
+            }
+            viewer::open_pdf(viewer_config, &pdf_path, None)
+        }
+        (Action::OpenNote, Hit::Note(m)) => {
+            viewer::open_note(viewer_config, &m.path, m.line_number)
+        }
+        (Action::OpenNote, Hit::Pdf(_)) => {
+            let typst_file = crate::find_typst_file(&base_path.join("summary"))?;
+            viewer::open_note(viewer_config, &typst_file, 1)
+        }
+        (Action::EditCitation, _) => crate::edit_citation(conn, hit.paper_id()).await,
+        (Action::Retag, _) => crate::retag_paper(conn, hit.paper_id()).await,
+    }
+}
+
+/// Let the user pick search results and act on them until they press Esc.
+pub async fn browse_hits(
+    conn: &libsql::Connection,
+    viewer_config: &ViewerConfig,
+    hits: Vec<Hit>,
+) -> Result<()> {
+    if hits.is_empty() {
+        println!("No matches found.");
+        return Ok(());
+    }
+
+    let mut cursor = 0;
+    loop {
+        let Some(selection) = Select::new("Select a result (Esc to quit):", hits.iter().collect())
+            .with_starting_cursor(cursor)
+            .raw_prompt_skippable()?
+        else {
+            break;
+        };
+        cursor = selection.index;
+        let hit = selection.value;
+
+        // Esc here goes back to the results
+        let Some(choice) = Select::new("Action:", choices(hit)).prompt_skippable()? else {
+            continue;
+        };
+        // A failed action should not end the session
+        if let Err(e) = run_action(conn, viewer_config, hit, choice.action).await {
+            eprintln!("{:#}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,324 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use inquire::{MultiSelect, Select};
+use std::fmt;
+
+use crate::library::Library;
+use crate::search;
+
+/// A paper in a collection, in collection order.
+struct Entry {
+    paper_id: u32,
+    position: i64,
+    path: String,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}. {}", self.position, self.path)
+    }
+}
+
+/// The id of the collection called `name`.
+pub async fn get_collection_id(conn: &libsql::Connection, name: &str) -> Result<u32> {
+    let mut rows = conn
+        .query("SELECT id FROM collections WHERE name = ?1", [name])
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok(row.get(0)?),
+        None => anyhow::bail!(
+            "Collection '{}' not found. Create it with `papr collection create`.",
+            name
+        ),
+    }
+}
+
+async fn get_entries(
+    conn: &libsql::Connection,
+    library: &Library,
+    collection_id: u32,
+) -> Result<Vec<Entry>> {
+    let mut rows = conn
+        .query(
+            "SELECT cp.paper_id, cp.position, p.canonical_base_path
+             FROM collection_papers cp JOIN papers p ON p.id = cp.paper_id
+             WHERE cp.collection_id = ?1 AND p.deleted_at IS NULL
+             ORDER BY cp.position",
+            [collection_id],
+        )
+        .await?;
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next().await? {
+        entries.push(Entry {
+            paper_id: row.get(0)?,
+            position: row.get(1)?,
+            path: library.resolve_string(&row.get::<String>(2)?),
+        });
+    }
+    Ok(entries)
+}
+
+/// Store `paper_ids` as the order of a collection, numbered from 1. Papers
+/// in the trash are not shown to the user, so they keep their relative order
+/// after the others.
+async fn set_order(conn: &libsql::Connection, collection_id: u32, paper_ids: &[u32]) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT paper_id FROM collection_papers WHERE collection_id = ?1 ORDER BY position",
+            [collection_id],
+        )
+        .await?;
+    let mut order = paper_ids.to_vec();
+    while let Some(row) = rows.next().await? {
+        let paper_id: u32 = row.get(0)?;
+        if !order.contains(&paper_id) {
+            order.push(paper_id);
+        }
+    }
+
+    let tx = conn.transaction().await?;
+    for (i, paper_id) in order.iter().enumerate() {
+        tx.execute(
+            "UPDATE collection_papers SET position = ?1 WHERE collection_id = ?2 AND paper_id = ?3",
+            (i as i64 + 1, collection_id, *paper_id),
+        )
+        .await?;
+    }
+    tx.commit()
+        .await
+        .context("Error updating collection order.")?;
+    Ok(())
+}
+
+pub async fn handle_collection_create(conn: &libsql::Connection, name: String) -> Result<()> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        anyhow::bail!("Collection name cannot be empty.");
+    }
+    let created = conn
+        .execute(
+            "INSERT OR IGNORE INTO collections (name, created_at) VALUES (?1, ?2)",
+            (name.clone(), Local::now().format("%Y-%m-%d").to_string()),
+        )
+        .await
+        .context("Error creating collection.")?;
+    if created == 0 {
+        anyhow::bail!("Collection '{}' already exists.", name);
+    }
+    println!("Created collection '{}'.", name);
+    Ok(())
+}
+
+pub async fn handle_collection_delete(conn: &libsql::Connection, name: String) -> Result<()> {
+    let id = get_collection_id(conn, &name).await?;
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "DELETE FROM collection_papers WHERE collection_id = ?1",
+        [id],
+    )
+    .await?;
+    tx.execute("DELETE FROM collections WHERE id = ?1", [id])
+        .await?;
+    tx.commit().await?;
+    println!(
+        "Deleted collection '{}'. Its papers were not changed.",
+        name
+    );
+    Ok(())
+}
+
+pub async fn handle_collection_list(conn: &libsql::Connection) -> Result<()> {
+    let mut rows = conn
+        .query(
+            "SELECT c.name, COUNT(p.id) FROM collections c
+             LEFT JOIN collection_papers cp ON cp.collection_id = c.id
+             LEFT JOIN papers p ON p.id = cp.paper_id AND p.deleted_at IS NULL
+             GROUP BY c.id ORDER BY c.name",
+            (),
+        )
+        .await?;
+    let mut count = 0;
+    while let Some(row) = rows.next().await? {
+        count += 1;
+        println!(
+            "{} ({} paper(s))",
+            row.get::<String>(0)?,
+            row.get::<u32>(1)?
+        );
+    }
+    if count == 0 {
+        println!("No collections yet. Create one with `papr collection create`.");
+    }
+    Ok(())
+}
+
+pub async fn handle_collection_add(
+    conn: &libsql::Connection,
+    library: &Library,
+    name: String,
+    query: String,
+) -> Result<()> {
+    let id = get_collection_id(conn, &name).await?;
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selections = MultiSelect::new(
+        &format!("Select papers to add to '{}':", name),
+        matching_papers,
+    )
+    .prompt()
+    .context("No papers selected.")?;
+
+    let tx = conn.transaction().await?;
+    for paper in &paper_selections {
+        // New papers go to the end of the list
+        let added = tx
+            .execute(
+                "INSERT OR IGNORE INTO collection_papers (collection_id, paper_id, position)
+                 SELECT ?1, ?2, COALESCE(MAX(position), 0) + 1
+                 FROM collection_papers WHERE collection_id = ?1",
+                (id, paper.id),
+            )
+            .await?;
+        if added == 0 {
+            println!("{} is already in '{}'.", paper.canonical_base_path, name);
+        } else {
+            println!("Added {} to '{}'.", paper.canonical_base_path, name);
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn handle_collection_remove(
+    conn: &libsql::Connection,
+    library: &Library,
+    name: String,
+) -> Result<()> {
+    let id = get_collection_id(conn, &name).await?;
+    let entries = get_entries(conn, library, id).await?;
+    if entries.is_empty() {
+        anyhow::bail!("Collection '{}' is empty.", name);
+    }
+    let selections = MultiSelect::new(
+        &format!("Select papers to remove from '{}':", name),
+        entries,
+    )
+    .prompt()
+    .context("No papers selected.")?;
+
+    for entry in &selections {
+        conn.execute(
+            "DELETE FROM collection_papers WHERE collection_id = ?1 AND paper_id = ?2",
+            (id, entry.paper_id),
+        )
+        .await?;
+        println!("Removed {} from '{}'.", entry.path, name);
+    }
+
+    let remaining = get_entries(conn, library, id).await?;
+    let order = remaining.iter().map(|e| e.paper_id).collect::<Vec<_>>();
+    set_order(conn, id, &order).await
+}
+
+pub async fn handle_collection_reorder(
+    conn: &libsql::Connection,
+    library: &Library,
+    name: String,
+) -> Result<()> {
+    let id = get_collection_id(conn, &name).await?;
+    let mut order = get_entries(conn, library, id).await?;
+    if order.len() < 2 {
+        anyhow::bail!("Collection '{}' has nothing to reorder.", name);
+    }
+
+    loop {
+        // Renumber for display after every move
+        for (i, entry) in order.iter_mut().enumerate() {
+            entry.position = i as i64 + 1;
+        }
+        let labels = order.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        let Some(selection) =
+            Select::new("Select a paper to move (Esc when done):", labels.clone())
+                .prompt_skippable()?
+        else {
+            break;
+        };
+        let from = labels.iter().position(|l| *l == selection).unwrap_or(0);
+
+        let positions = (1..=order.len()).map(|i| i.to_string()).collect::<Vec<_>>();
+        let to = Select::new("Move to position:", positions)
+            .with_starting_cursor(from)
+            .prompt()?
+            .parse::<usize>()?
+            - 1;
+
+        let entry = order.remove(from);
+        order.insert(to, entry);
+    }
+
+    let paper_ids = order.iter().map(|e| e.paper_id).collect::<Vec<_>>();
+    set_order(conn, id, &paper_ids).await?;
+    println!("Saved the order of '{}'.", name);
+    Ok(())
+}
+
+pub async fn handle_collection_show(
+    conn: &libsql::Connection,
+    library: &Library,
+    name: String,
+) -> Result<()> {
+    let id = get_collection_id(conn, &name).await?;
+    let entries = get_entries(conn, library, id).await?;
+    println!("{}:", name);
+    if entries.is_empty() {
+        println!("  (empty)");
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        println!("  {}. {}", i + 1, entry.path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::memory_db;
+
+    async fn positions(conn: &libsql::Connection) -> Vec<(u32, i64)> {
+        let mut rows = conn
+            .query(
+                "SELECT paper_id, position FROM collection_papers ORDER BY position",
+                (),
+            )
+            .await
+            .unwrap();
+        let mut positions = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            positions.push((row.get(0).unwrap(), row.get(1).unwrap()));
+        }
+        positions
+    }
+
+    #[tokio::test]
+    async fn reordering_keeps_trashed_papers_last() {
+        let conn = memory_db().await;
+        conn.execute_batch(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation) VALUES
+                ('a', '', '2024-01-01', ''), ('b', '', '2024-01-01', ''),
+                ('c', '', '2024-01-01', ''), ('d', '', '2024-01-01', '');
+             UPDATE papers SET deleted_at = '2024-02-01' WHERE id = 2;
+             INSERT INTO collections (name, created_at) VALUES ('thesis', '2024-01-01');
+             INSERT INTO collection_papers (collection_id, paper_id, position) VALUES
+                (1, 1, 1), (1, 2, 2), (1, 3, 3), (1, 4, 4);",
+        )
+        .await
+        .unwrap();
+
+        let id = get_collection_id(&conn, "thesis").await.unwrap();
+        set_order(&conn, id, &[4, 1, 3]).await.unwrap();
+        assert_eq!(positions(&conn).await, [(4, 1), (1, 2), (3, 3), (2, 4)]);
+        assert!(get_collection_id(&conn, "missing").await.is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// User configuration, read from `config.toml` in the papr config directory.
+/// Every field has a default, so a missing file or missing keys are fine.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub trash: TrashConfig,
+    pub library: LibraryConfig,
+    pub download: DownloadConfig,
+    pub arxiv: ArxivConfig,
+    pub naming: NamingConfig,
+    pub viewer: ViewerConfig,
+    pub templates: TemplateConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TemplateConfig {
+    /// Template for new notes, named after its file in the `templates`
+    /// config directory without `.typ`. Defaults to the built-in `review`.
+    pub default: Option<String>,
+    /// Templates for papers with a given tag, e.g. `math = "proofs"`.
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ViewerConfig {
+    /// Command used to open PDFs, e.g. `zathura -P {page} {file}`. `{page}`
+    /// is 1 unless a page was asked for. Defaults to the system PDF viewer.
+    pub command: Option<String>,
+    /// Command used to open notes, e.g. `nvim +{line} {file}`. Defaults to
+    /// `$VISUAL` or `$EDITOR` with a `+{line}` argument.
+    pub editor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct NamingConfig {
+    /// Pattern for new paper directory names. Supports `{title}`,
+    /// `{short_title}`, `{first_author}` and `{year}`.
+    pub pattern: String,
+    /// Maximum length of a directory name, excluding any collision suffix.
+    pub max_length: usize,
+}
+
+impl Default for NamingConfig {
+    fn default() -> Self {
+        Self {
+            pattern: "{title}".to_string(),
+            max_length: 80,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ArxivConfig {
+    /// Endpoint of the arXiv query API, checked by `papr outdated`.
+    pub api_url: String,
+    /// Base URL that `<id>v<version>` is appended to when downloading a PDF.
+    pub pdf_url: String,
+}
+
+impl Default for ArxivConfig {
+    fn default() -> Self {
+        Self {
+            api_url: "https://export.arxiv.org/api/query".to_string(),
+            pdf_url: "https://arxiv.org/pdf".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    pub connect_timeout_secs: u64,
+    /// Maximum time to wait for more data before a download is considered stalled.
+    pub read_timeout_secs: u64,
+    /// Number of extra attempts after a failed download.
+    pub retries: u32,
+    /// Delay before the first retry, doubled on every further attempt.
+    pub backoff_ms: u64,
+    /// Some publishers reject requests without a browser-like User-Agent.
+    pub user_agent: String,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 30,
+            retries: 3,
+            backoff_ms: 500,
+            user_agent: concat!("papr/", env!("CARGO_PKG_VERSION")).to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct LibraryConfig {
+    /// Library root used by the global database. Paper paths under this
+    /// directory are stored relative to it, and the database keeps using it
+    /// once set. Defaults to the home directory.
+    pub global_root: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// Number of days a removed paper stays in the trash before being purged.
+    /// A value of 0 disables automatic purging.
+    pub retention_days: u32,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self { retention_days: 30 }
+    }
+}
+
+pub fn get_config_dir() -> Result<PathBuf> {
+    let proj_dirs = ProjectDirs::from("com", "", "papr")
+        .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+    Ok(proj_dirs.config_dir().to_path_buf())
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let config_path = get_config_dir()?.join("config.toml");
+        if !config_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&config_path)
+            .with_context(|| format!("Error reading config file {:?}.", config_path))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Error parsing config file {:?}.", config_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_keep_their_defaults() {
+        let config: Config = toml::from_str(
+            "[trash]\nretention_days = 0\n\n[download]\nretries = 1\n\n\
+             [templates.tags]\nmath = \"proofs\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.trash.retention_days, 0);
+        assert_eq!(config.download.retries, 1);
+        assert_eq!(config.download.backoff_ms, 500);
+        assert_eq!(config.naming.pattern, "{title}");
+        assert_eq!(config.naming.max_length, 80);
+        assert_eq!(config.templates.tags["math"], "proofs");
+        assert_eq!(config.templates.default, None);
+        assert_eq!(config.library.global_root, None);
+    }
+
+    #[test]
+    fn rejects_wrongly_typed_values() {
+        assert!(toml::from_str::<Config>("[trash]\nretention_days = \"soon\"\n").is_err());
+    }
+}
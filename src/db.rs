@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+
+const BASE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS papers (
+        id INTEGER PRIMARY KEY,
+        canonical_base_path TEXT NOT NULL UNIQUE,
+        url TEXT NOT NULL,
+        date_added TEXT NOT NULL,
+        citation TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tags (
+        id INTEGER PRIMARY KEY,
+        name TEXT UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS paper_tags (
+        paper_id INTEGER,
+        tag_id INTEGER,
+        FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE,
+        FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+    );";
+
+/// Schema changes applied on top of `BASE_SCHEMA`, in order.
+/// The number of applied migrations is tracked in `PRAGMA user_version`,
+/// so entries must never be edited or reordered once released.
+const MIGRATIONS: &[&str] = &[
+    // Soft delete: removed papers keep their row and point at their trash directory
+    "ALTER TABLE papers ADD COLUMN deleted_at TEXT;
+     ALTER TABLE papers ADD COLUMN trash_path TEXT;",
+    // Per-library settings such as the library root
+    "CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+     );",
+    // `INSERT OR IGNORE INTO paper_tags` needs a uniqueness constraint to ignore anything
+    "DELETE FROM paper_tags WHERE rowid NOT IN
+        (SELECT MIN(rowid) FROM paper_tags GROUP BY paper_id, tag_id);
+     CREATE UNIQUE INDEX IF NOT EXISTS paper_tags_unique ON paper_tags (paper_id, tag_id);",
+    // Extra files stored under `attachments/` in the paper directory
+    "CREATE TABLE IF NOT EXISTS attachments (
+        id INTEGER PRIMARY KEY,
+        paper_id INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        file_name TEXT NOT NULL,
+        source TEXT NOT NULL,
+        date_added TEXT NOT NULL,
+        UNIQUE(paper_id, file_name),
+        FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE
+     );",
+    // Identifiers used to recognise duplicate papers
+    "ALTER TABLE papers ADD COLUMN pdf_hash TEXT;
+     ALTER TABLE papers ADD COLUMN doi TEXT;
+     ALTER TABLE papers ADD COLUMN arxiv_id TEXT;",
+    // arXiv version of the PDF in the paper directory, checked by `papr outdated`
+    "ALTER TABLE papers ADD COLUMN arxiv_version INTEGER;",
+    // Reading status and queue, see `ReadingStatus` for the possible values
+    "ALTER TABLE papers ADD COLUMN status TEXT NOT NULL DEFAULT 'inbox';
+     ALTER TABLE papers ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+     CREATE TABLE IF NOT EXISTS status_changes (
+        id INTEGER PRIMARY KEY,
+        paper_id INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        changed_at TEXT NOT NULL,
+        FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE
+     );",
+    // Personal 1-5 rating, favourites and a note on why the paper matters
+    "ALTER TABLE papers ADD COLUMN rating INTEGER;
+     ALTER TABLE papers ADD COLUMN starred INTEGER NOT NULL DEFAULT 0;
+     ALTER TABLE papers ADD COLUMN relevance TEXT;",
+    // Time spent in `papr notes`, an open session has no `ended_at`
+    "CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        paper_id INTEGER NOT NULL,
+        started_at TEXT NOT NULL,
+        ended_at TEXT,
+        FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE
+     );",
+    // Named, ordered reading lists, independent of tags
+    "CREATE TABLE IF NOT EXISTS collections (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        created_at TEXT NOT NULL
+     );
+     CREATE TABLE IF NOT EXISTS collection_papers (
+        collection_id INTEGER NOT NULL,
+        paper_id INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        UNIQUE(collection_id, paper_id),
+        FOREIGN KEY(collection_id) REFERENCES collections(id) ON DELETE CASCADE,
+        FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE
+     );",
+    // Title as entered when adding, passed to the notes through `sys.inputs`
+    "ALTER TABLE papers ADD COLUMN title TEXT;",
+    // `@papr:<id>` mentions in the notes of `paper_id`, see `papr backlinks`.
+    // The target has no foreign key, notes can mention any id.
+    "CREATE TABLE IF NOT EXISTS note_links (
+        id INTEGER PRIMARY KEY,
+        paper_id INTEGER NOT NULL,
+        target_id INTEGER NOT NULL,
+        file TEXT NOT NULL,
+        line INTEGER NOT NULL,
+        FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE
+     );",
+    // Authors and year from the PDF's metadata when adding, so `sys.inputs`
+    // agrees with what the notes were filled in with
+    "ALTER TABLE papers ADD COLUMN authors TEXT;
+     ALTER TABLE papers ADD COLUMN year TEXT;",
+];
+
+pub async fn init_schema(conn: &libsql::Connection) -> Result<()> {
+    // SQLite leaves foreign key enforcement off unless asked, per connection
+    conn.execute("PRAGMA foreign_keys = ON", ())
+        .await
+        .context("Error enabling foreign keys.")?;
+
+    conn.execute_batch(BASE_SCHEMA)
+        .await
+        .context("Error initializing DB schema.")?;
+
+    let version: i64 = conn
+        .query("PRAGMA user_version", ())
+        .await?
+        .next()
+        .await?
+        .map(|row| row.get(0))
+        .transpose()?
+        .unwrap_or(0);
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        conn.execute_batch(&format!(
+            "BEGIN;\n{}\nPRAGMA user_version = {};\nCOMMIT;",
+            migration,
+            i + 1
+        ))
+        .await
+        .with_context(|| format!("Error applying DB migration {}.", i + 1))?;
+    }
+
+    Ok(())
+}
+
+/// Tables whose rows belong to a single paper, via a `paper_id` column.
+const PAPER_TABLES: &[&str] = &[
+    "paper_tags",
+    "attachments",
+    "status_changes",
+    "sessions",
+    "collection_papers",
+    "note_links",
+];
+
+/// Delete everything linked to a paper, but not the paper row itself.
+pub async fn delete_paper_links(conn: &libsql::Connection, paper_id: u32) -> Result<()> {
+    for table in PAPER_TABLES {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE paper_id = ?1", table),
+            [paper_id],
+        )
+        .await
+        .with_context(|| format!("Error deleting from {}.", table))?;
+    }
+    Ok(())
+}
+
+/// Names of a paper's tags, sorted.
+pub async fn get_paper_tags(conn: &libsql::Connection, paper_id: u32) -> Result<Vec<String>> {
+    let mut rows = conn
+        .query(
+            "SELECT t.name FROM tags t JOIN paper_tags pt ON pt.tag_id = t.id
+             WHERE pt.paper_id = ?1 ORDER BY t.name",
+            [paper_id],
+        )
+        .await?;
+    let mut tags = Vec::new();
+    while let Some(row) = rows.next().await? {
+        tags.push(row.get(0)?);
+    }
+    Ok(tags)
+}
+
+pub async fn get_meta(conn: &libsql::Connection, key: &str) -> Result<Option<String>> {
+    let mut rows = conn
+        .query("SELECT value FROM meta WHERE key = ?1", [key])
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn set_meta(conn: &libsql::Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+        [key, value],
+    )
+    .await
+    .context("Error updating meta table.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A fresh in-memory database with every migration applied.
+    pub(crate) async fn memory_db() -> libsql::Connection {
+        let db = libsql::Builder::new_local(":memory:")
+            .build()
+            .await
+            .unwrap();
+        let conn = db.connect().unwrap();
+        init_schema(&conn).await.unwrap();
+        conn
+    }
+
+    async fn user_version(conn: &libsql::Connection) -> i64 {
+        let mut rows = conn.query("PRAGMA user_version", ()).await.unwrap();
+        rows.next().await.unwrap().unwrap().get(0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn applies_every_migration_once() {
+        let conn = memory_db().await;
+        assert_eq!(user_version(&conn).await, MIGRATIONS.len() as i64);
+
+        // Reopening an up to date database changes nothing
+        init_schema(&conn).await.unwrap();
+        assert_eq!(user_version(&conn).await, MIGRATIONS.len() as i64);
+    }
+
+    #[tokio::test]
+    async fn migrates_existing_papers() {
+        let db = libsql::Builder::new_local(":memory:")
+            .build()
+            .await
+            .unwrap();
+        let conn = db.connect().unwrap();
+        conn.execute_batch(BASE_SCHEMA).await.unwrap();
+        conn.execute(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+             VALUES ('/papers/a', '', '2024-01-01', '')",
+            (),
+        )
+        .await
+        .unwrap();
+
+        init_schema(&conn).await.unwrap();
+        let mut rows = conn
+            .query("SELECT status, deleted_at FROM papers", ())
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "inbox");
+        assert_eq!(row.get::<Option<String>>(1).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn deletes_rows_of_every_paper_table() {
+        let conn = memory_db().await;
+        for id in [1, 2] {
+            conn.execute_batch(&format!(
+                "INSERT INTO papers (id, canonical_base_path, url, date_added, citation)
+                    VALUES ({id}, 'p{id}', '', '2024-01-01', '');
+                 INSERT INTO tags (id, name) VALUES ({id}, 't{id}');
+                 INSERT INTO collections (id, name, created_at) VALUES ({id}, 'c{id}', '2024-01-01');
+                 INSERT INTO paper_tags (paper_id, tag_id) VALUES ({id}, {id});
+                 INSERT INTO attachments (paper_id, kind, file_name, source, date_added)
+                    VALUES ({id}, 'code', 'a.zip', 'a.zip', '2024-01-01');
+                 INSERT INTO status_changes (paper_id, status, changed_at)
+                    VALUES ({id}, 'read', '2024-01-01');
+                 INSERT INTO sessions (paper_id, started_at) VALUES ({id}, '2024-01-01');
+                 INSERT INTO collection_papers (collection_id, paper_id, position)
+                    VALUES ({id}, {id}, 1);
+                 INSERT INTO note_links (paper_id, target_id, file, line)
+                    VALUES ({id}, 3, 'main.typ', 1);"
+            ))
+            .await
+            .unwrap();
+        }
+
+        delete_paper_links(&conn, 1).await.unwrap();
+
+        for table in PAPER_TABLES {
+            let mut rows = conn
+                .query(
+                    &format!("SELECT paper_id FROM {} ORDER BY paper_id", table),
+                    (),
+                )
+                .await
+                .unwrap();
+            let mut ids = Vec::new();
+            while let Some(row) = rows.next().await.unwrap() {
+                ids.push(row.get::<u32>(0).unwrap());
+            }
+            assert_eq!(ids, [2], "rows left in {}", table);
+        }
+        let mut rows = conn.query("SELECT COUNT(*) FROM papers", ()).await.unwrap();
+        assert_eq!(
+            rows.next().await.unwrap().unwrap().get::<u32>(0).unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_meta_values() {
+        let conn = memory_db().await;
+        assert_eq!(get_meta(&conn, "library_root").await.unwrap(), None);
+        set_meta(&conn, "library_root", "/a").await.unwrap();
+        set_meta(&conn, "library_root", "/b").await.unwrap();
+        assert_eq!(
+            get_meta(&conn, "library_root").await.unwrap().as_deref(),
+            Some("/b")
+        );
+    }
+}
@@ -0,0 +1,816 @@
+use anyhow::{Context, Result};
+use inquire::Select;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::arxiv::{canonical_id, find_arxiv_ref};
+use crate::attachments::{ATTACHMENTS_DIR, free_file_name};
+use crate::fsutil::copy_dir_all;
+use crate::library::Library;
+use crate::{tag_paper, trash};
+
+/// Share of title words two papers need in common to be flagged as duplicates.
+const TITLE_SIMILARITY_THRESHOLD: f64 = 0.8;
+
+static DOI: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\b(10\.\d{4,9}/[^\s"<>{},]+)"#).unwrap());
+
+/// Everything used to recognise the same paper under different names.
+#[derive(Debug, Default)]
+pub struct Fingerprint {
+    pub pdf_hash: Option<String>,
+    pub doi: Option<String>,
+    pub arxiv_id: Option<String>,
+    pub arxiv_version: Option<u32>,
+    pub url: Option<String>,
+    pub title: String,
+}
+
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).with_context(|| format!("Error reading {:?}.", path))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn find_doi(texts: &[&str]) -> Option<String> {
+    texts
+        .iter()
+        .find_map(|text| DOI.captures(text))
+        .map(|c| c[1].trim_end_matches(['.', ';']).to_lowercase())
+}
+
+/// Strip the parts of a URL that do not identify the document.
+fn normalize_url(url: &str) -> Option<String> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let without_query = without_scheme.split(['?', '#']).next().unwrap_or_default();
+    let normalized = without_query
+        .trim_start_matches("www.")
+        .trim_end_matches('/')
+        .to_lowercase();
+    (!normalized.is_empty()).then_some(normalized)
+}
+
+fn title_words(title: &str) -> HashSet<String> {
+    title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn title_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (title_words(a), title_words(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+/// Title of a paper as derived from its directory name.
+pub fn title_from_path(path: &Path) -> String {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .replace('_', " ")
+}
+
+impl Fingerprint {
+    pub fn new(title: &str, url: &str, citation: &str, pdf_path: Option<&Path>) -> Result<Self> {
+        let arxiv = find_arxiv_ref(&[url, citation]);
+        Ok(Self {
+            pdf_hash: pdf_path.filter(|p| p.exists()).map(hash_file).transpose()?,
+            doi: find_doi(&[url, citation]),
+            arxiv_version: arxiv.as_ref().and_then(|a| a.version),
+            arxiv_id: arxiv.map(|a| a.id),
+            url: normalize_url(url),
+            title: title.to_string(),
+        })
+    }
+
+    /// Which identifier `other` shares with this paper, if any.
+    fn identifier_match(&self, other: &Fingerprint) -> Option<String> {
+        let same = |a: &Option<String>, b: &Option<String>| a.is_some() && a == b;
+        if same(&self.pdf_hash, &other.pdf_hash) {
+            Some("identical PDF".to_string())
+        } else if same(&self.doi, &other.doi) {
+            Some(format!(
+                "same DOI {}",
+                self.doi.as_deref().unwrap_or_default()
+            ))
+        } else if same(&self.arxiv_id, &other.arxiv_id) {
+            Some(format!(
+                "same arXiv id {}",
+                self.arxiv_id.as_deref().unwrap_or_default()
+            ))
+        } else if same(&self.url, &other.url) {
+            Some("same URL".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Why `other` looks like the same paper, if it does.
+    fn matches(&self, other: &Fingerprint) -> Option<String> {
+        self.identifier_match(other).or_else(|| {
+            let similarity = title_similarity(&self.title, &other.title);
+            (similarity >= TITLE_SIMILARITY_THRESHOLD)
+                .then(|| format!("similar title ({:.0}% overlap)", similarity * 100.0))
+        })
+    }
+}
+
+/// Store the identifying fields of a paper so later checks need not recompute
+/// them. Fields missing from `fingerprint` keep their stored value.
+pub async fn store_fingerprint(
+    conn: &libsql::Connection,
+    paper_id: u32,
+    fingerprint: &Fingerprint,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE papers SET pdf_hash = COALESCE(?1, pdf_hash), doi = COALESCE(?2, doi),
+         arxiv_id = COALESCE(?3, arxiv_id), arxiv_version = COALESCE(?4, arxiv_version)
+         WHERE id = ?5",
+        (
+            fingerprint.pdf_hash.clone(),
+            fingerprint.doi.clone(),
+            fingerprint.arxiv_id.clone(),
+            fingerprint.arxiv_version,
+            paper_id,
+        ),
+    )
+    .await
+    .context("Error storing paper fingerprint.")?;
+    Ok(())
+}
+
+struct KnownPaper {
+    id: u32,
+    path: String,
+    fingerprint: Fingerprint,
+}
+
+/// Fingerprints of every paper in the library, hashing PDFs that were
+/// added before hashes were recorded.
+async fn get_known_papers(conn: &libsql::Connection, library: &Library) -> Result<Vec<KnownPaper>> {
+    let mut rows = conn
+        .query(
+            "SELECT id, canonical_base_path, url, citation, pdf_hash, doi, arxiv_id, title
+             FROM papers WHERE deleted_at IS NULL ORDER BY id",
+            (),
+        )
+        .await?;
+
+    let mut papers = Vec::new();
+    let mut missing_hashes = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let id: u32 = row.get(0)?;
+        let path = library.resolve(&row.get::<String>(1)?);
+        let url: String = row.get(2)?;
+        let citation: String = row.get(3)?;
+        let pdf_hash: Option<String> = row.get(4)?;
+        let doi: Option<String> = row.get(5)?;
+        // Ids stored before they were canonicalised may be all lowercase
+        let arxiv_id = row.get::<Option<String>>(6)?.map(|id| canonical_id(&id));
+
+        // Directory names may not contain the title, depending on the naming pattern
+        let title = row
+            .get::<Option<String>>(7)?
+            .filter(|title| !title.trim().is_empty())
+            .unwrap_or_else(|| title_from_path(&path));
+        let mut fingerprint = Fingerprint::new(&title, &url, &citation, None)?;
+        fingerprint.doi = doi.or(fingerprint.doi);
+        fingerprint.arxiv_id = arxiv_id.or(fingerprint.arxiv_id);
+        fingerprint.pdf_hash = match pdf_hash {
+            Some(hash) => Some(hash),
+            None => {
+                let pdf_path = path.join("paper.pdf");
+                let hash = pdf_path
+                    .exists()
+                    .then(|| hash_file(&pdf_path))
+                    .transpose()?;
+                if hash.is_some() {
+                    missing_hashes.push(id);
+                }
+                hash
+            }
+        };
+
+        papers.push(KnownPaper {
+            id,
+            path: path.display().to_string(),
+            fingerprint,
+        });
+    }
+
+    for paper in papers.iter().filter(|p| missing_hashes.contains(&p.id)) {
+        store_fingerprint(conn, paper.id, &paper.fingerprint).await?;
+    }
+
+    Ok(papers)
+}
+
+pub struct DuplicateMatch {
+    pub id: u32,
+    pub path: String,
+    pub reason: String,
+    /// Matched on an identifier rather than only a similar title
+    pub certain: bool,
+}
+
+pub async fn find_duplicates(
+    conn: &libsql::Connection,
+    library: &Library,
+    fingerprint: &Fingerprint,
+) -> Result<Vec<DuplicateMatch>> {
+    Ok(get_known_papers(conn, library)
+        .await?
+        .into_iter()
+        .filter_map(|known| {
+            let certain = fingerprint.identifier_match(&known.fingerprint).is_some();
+            fingerprint
+                .matches(&known.fingerprint)
+                .map(|reason| DuplicateMatch {
+                    id: known.id,
+                    path: known.path,
+                    reason,
+                    certain,
+                })
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+pub enum DuplicateAction {
+    Merge,
+    Skip,
+    AddAnyway,
+}
+
+impl fmt::Display for DuplicateAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Merge => write!(
+                f,
+                "Merge into the existing paper (add tags, fill in URL and citation)"
+            ),
+            Self::Skip => write!(f, "Skip"),
+            Self::AddAnyway => write!(f, "Add anyway"),
+        }
+    }
+}
+
+/// Warn about likely duplicates and ask what to do. Returns the existing
+/// paper to merge into alongside the chosen action.
+pub fn prompt_duplicate_action(
+    duplicates: Vec<DuplicateMatch>,
+) -> Result<(DuplicateAction, Option<u32>)> {
+    if duplicates.is_empty() {
+        return Ok((DuplicateAction::AddAnyway, None));
+    }
+
+    println!("This paper looks like one already in your library:");
+    for duplicate in &duplicates {
+        println!(
+            "  {} (ID: {}): {}",
+            duplicate.path, duplicate.id, duplicate.reason
+        );
+    }
+
+    let action = Select::new(
+        "What should be done?",
+        vec![
+            DuplicateAction::Merge,
+            DuplicateAction::Skip,
+            DuplicateAction::AddAnyway,
+        ],
+    )
+    .prompt()?;
+
+    // Only one paper can be merged into, so let the user pick it
+    let target = match action {
+        DuplicateAction::Merge if duplicates.len() > 1 => {
+            let options = duplicates
+                .iter()
+                .map(|d| format!("{} (ID: {})", d.path, d.id))
+                .collect::<Vec<_>>();
+            let choice = Select::new("Merge into which paper?", options.clone()).prompt()?;
+            let index = options.iter().position(|o| *o == choice).unwrap_or(0);
+            Some(duplicates[index].id)
+        }
+        DuplicateAction::Merge => Some(duplicates[0].id),
+        _ => None,
+    };
+
+    Ok((action, target))
+}
+
+/// Add tags to an existing paper and fill in its URL and citation if empty.
+pub async fn merge_into(
+    conn: &libsql::Connection,
+    target_id: u32,
+    url: &str,
+    citation: &str,
+    tag_names: Vec<String>,
+) -> Result<()> {
+    let tx = conn.transaction().await?;
+    merge_details(&tx, target_id, url, citation, tag_names).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// `merge_into` without a transaction of its own.
+async fn merge_details(
+    conn: &libsql::Connection,
+    target_id: u32,
+    url: &str,
+    citation: &str,
+    tag_names: Vec<String>,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE papers SET url = ?1 WHERE id = ?2 AND url = ''",
+        (url.to_string(), target_id),
+    )
+    .await?;
+    conn.execute(
+        "UPDATE papers SET citation = ?1 WHERE id = ?2 AND citation = ''",
+        (citation.to_string(), target_id),
+    )
+    .await?;
+    tag_paper(conn, target_id, tag_names).await
+}
+
+async fn get_paper_details(
+    conn: &libsql::Connection,
+    id: u32,
+) -> Result<(String, String, Vec<String>)> {
+    let mut rows = conn
+        .query("SELECT url, citation FROM papers WHERE id = ?1", [id])
+        .await?;
+    let (url, citation) = match rows.next().await? {
+        Some(row) => (row.get(0)?, row.get(1)?),
+        None => anyhow::bail!("Paper ID {} not found in database.", id),
+    };
+
+    let mut rows = conn
+        .query(
+            "SELECT t.name FROM tags t JOIN paper_tags pt ON pt.tag_id = t.id WHERE pt.paper_id = ?1",
+            [id],
+        )
+        .await?;
+    let mut tags = Vec::new();
+    while let Some(row) = rows.next().await? {
+        tags.push(row.get(0)?);
+    }
+
+    Ok((url, citation, tags))
+}
+
+/// Copy the notes and attachments of a duplicate into the paper it is merged
+/// into, and carry over its rating, reading status, sessions, collections and
+/// backlinks where the kept paper has none of its own. The notes go into a
+/// `from_<directory>` subdirectory of `summary/` so nothing is overwritten.
+/// Everything copied is added to `copied` so the caller can remove it if the
+/// merge is not committed.
+async fn merge_paper_state(
+    conn: &libsql::Connection,
+    keep: &KnownPaper,
+    drop: &KnownPaper,
+    copied: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let keep_path = Path::new(&keep.path);
+    let drop_path = Path::new(&drop.path);
+
+    let notes = drop_path.join("summary");
+    if notes.is_dir() && fs::read_dir(&notes)?.next().is_some() {
+        let summary_dir = keep_path.join("summary");
+        let dir_name = drop_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("duplicate");
+        let target = summary_dir.join(free_file_name(&summary_dir, &format!("from_{}", dir_name)));
+        copied.push(target.clone());
+        copy_dir_all(&notes, &target)?;
+        println!("Copied notes of {} to {}", drop.path, target.display());
+    }
+
+    let mut rows = conn
+        .query(
+            "SELECT kind, file_name, source, date_added FROM attachments WHERE paper_id = ?1",
+            [drop.id],
+        )
+        .await?;
+    let mut attachments = Vec::new();
+    while let Some(row) = rows.next().await? {
+        attachments.push((
+            row.get::<String>(0)?,
+            row.get::<String>(1)?,
+            row.get::<String>(2)?,
+            row.get::<String>(3)?,
+        ));
+    }
+    let attachments_dir = keep_path.join(ATTACHMENTS_DIR);
+    for (kind, file_name, source, date_added) in attachments {
+        fs::create_dir_all(&attachments_dir).context("Error creating attachments directory.")?;
+        let new_name = free_file_name(&attachments_dir, &file_name);
+        let target = attachments_dir.join(&new_name);
+        copied.push(target.clone());
+        fs::copy(drop_path.join(ATTACHMENTS_DIR).join(&file_name), &target)
+            .with_context(|| format!("Error copying attachment {}.", file_name))?;
+        conn.execute(
+            "INSERT INTO attachments (paper_id, kind, file_name, source, date_added)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            (keep.id, kind, new_name, source, date_added),
+        )
+        .await
+        .context("Error updating attachments table.")?;
+    }
+
+    // A paper still in the inbox has not been looked at, so take the
+    // other paper's progress
+    conn.execute(
+        "UPDATE papers SET
+            rating = COALESCE(rating, (SELECT rating FROM papers WHERE id = ?2)),
+            relevance = COALESCE(relevance, (SELECT relevance FROM papers WHERE id = ?2)),
+            starred = MAX(starred, (SELECT starred FROM papers WHERE id = ?2)),
+            priority = MAX(priority, (SELECT priority FROM papers WHERE id = ?2)),
+            status = CASE WHEN status = 'inbox'
+                THEN (SELECT status FROM papers WHERE id = ?2) ELSE status END
+         WHERE id = ?1",
+        (keep.id, drop.id),
+    )
+    .await
+    .context("Error merging paper details.")?;
+    for table in ["status_changes", "sessions"] {
+        conn.execute(
+            &format!("UPDATE {} SET paper_id = ?1 WHERE paper_id = ?2", table),
+            (keep.id, drop.id),
+        )
+        .await?;
+    }
+    // Appended, since the duplicate's position may already be taken
+    conn.execute(
+        "INSERT OR IGNORE INTO collection_papers (collection_id, paper_id, position)
+         SELECT cp.collection_id, ?1,
+             (SELECT MAX(position) + 1 FROM collection_papers WHERE collection_id = cp.collection_id)
+         FROM collection_papers cp WHERE cp.paper_id = ?2",
+        (keep.id, drop.id),
+    )
+    .await
+    .context("Error merging collections.")?;
+    conn.execute(
+        "UPDATE note_links SET target_id = ?1 WHERE target_id = ?2",
+        (keep.id, drop.id),
+    )
+    .await
+    .context("Error merging note links.")?;
+
+    Ok(())
+}
+
+/// Merge `drop` into `keep` and trash it. The DB changes are made in one
+/// transaction, and the directory is only moved to the trash once that has
+/// been committed.
+async fn merge_duplicate(
+    conn: &libsql::Connection,
+    library: &Library,
+    trash_dir: &Path,
+    keep: &KnownPaper,
+    drop: &KnownPaper,
+) -> Result<()> {
+    let trash_path = trash::trash_path(trash_dir, drop.id, &drop.path)?;
+    // Removed again if anything fails
+    let mut copied = Vec::new();
+
+    let res = async {
+        let tx = conn.transaction().await?;
+        let (url, citation, tags) = get_paper_details(&tx, drop.id).await?;
+        merge_details(&tx, keep.id, &url, &citation, tags).await?;
+        merge_paper_state(&tx, keep, drop, &mut copied).await?;
+        trash::mark_trashed(&tx, library, drop.id, &trash_path).await?;
+        tx.commit().await.context("Error merging duplicate papers.")
+    }
+    .await;
+
+    if res.is_err() {
+        for path in copied {
+            let _ = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+        }
+    }
+    res?;
+
+    trash::move_to_trash(conn, drop.id, &drop.path, &trash_path).await?;
+    println!("Moved '{}' to the trash.", drop.path);
+    Ok(())
+}
+
+#[derive(Debug)]
+enum PairAction {
+    KeepFirst,
+    KeepSecond,
+    Skip,
+}
+
+impl fmt::Display for PairAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::KeepFirst => write!(f, "Merge the second into the first, trash the second"),
+            Self::KeepSecond => write!(f, "Merge the first into the second, trash the first"),
+            Self::Skip => write!(f, "Not duplicates, skip"),
+        }
+    }
+}
+
+pub async fn handle_dedupe(
+    conn: &libsql::Connection,
+    library: &Library,
+    trash_dir: &Path,
+) -> Result<()> {
+    println!("Fingerprinting papers...");
+    let papers = get_known_papers(conn, library).await?;
+
+    let mut pairs = Vec::new();
+    for (i, a) in papers.iter().enumerate() {
+        for b in &papers[i + 1..] {
+            if let Some(reason) = a.fingerprint.matches(&b.fingerprint) {
+                pairs.push((a, b, reason));
+            }
+        }
+    }
+
+    if pairs.is_empty() {
+        println!("No duplicates found.");
+        return Ok(());
+    }
+
+    let mut trashed = HashSet::new();
+    for (a, b, reason) in pairs {
+        if trashed.contains(&a.id) || trashed.contains(&b.id) {
+            continue;
+        }
+
+        println!(
+            "\nPossible duplicates ({}):\n  1. {} (ID: {})\n  2. {} (ID: {})",
+            reason, a.path, a.id, b.path, b.id
+        );
+        let action = Select::new(
+            "What should be done?",
+            vec![
+                PairAction::KeepFirst,
+                PairAction::KeepSecond,
+                PairAction::Skip,
+            ],
+        )
+        .prompt()?;
+
+        let (keep, drop) = match action {
+            PairAction::KeepFirst => (a, b),
+            PairAction::KeepSecond => (b, a),
+            PairAction::Skip => continue,
+        };
+
+        merge_duplicate(conn, library, trash_dir, keep, drop).await?;
+        trashed.insert(drop.id);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::tests::memory_db;
+
+    fn fingerprint(title: &str, url: &str, citation: &str) -> Fingerprint {
+        Fingerprint::new(title, url, citation, None).unwrap()
+    }
+
+    #[test]
+    fn finds_doi_without_trailing_punctuation() {
+        assert_eq!(
+            find_doi(&["", "Nature 1, 2. doi:10.1038/NATURE14539."]).as_deref(),
+            Some("10.1038/nature14539")
+        );
+        assert_eq!(find_doi(&["https://example.org/10.1/x"]), None);
+    }
+
+    #[test]
+    fn normalizes_urls() {
+        assert_eq!(
+            normalize_url(" https://www.Example.org/paper/?utm=1#top ").as_deref(),
+            Some("example.org/paper")
+        );
+        assert_eq!(normalize_url(""), None);
+    }
+
+    #[test]
+    fn matches_shared_identifiers_before_titles() {
+        let a = fingerprint("A", "https://arxiv.org/abs/1706.03762v1", "");
+        let b = fingerprint("B", "", "arXiv:1706.03762v5");
+        assert_eq!(a.matches(&b).as_deref(), Some("same arXiv id 1706.03762"));
+
+        let a = fingerprint("A", "", "doi:10.1234/abc");
+        let b = fingerprint("B", "https://doi.org/10.1234/ABC", "");
+        assert_eq!(a.matches(&b).as_deref(), Some("same DOI 10.1234/abc"));
+
+        assert!(
+            fingerprint("A", "", "")
+                .matches(&fingerprint("B", "", ""))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn matches_similar_titles() {
+        let a = fingerprint("Attention Is All You Need", "", "");
+        let b = fingerprint("attention_is_all_you_need", "", "");
+        assert_eq!(
+            a.matches(&b).as_deref(),
+            Some("similar title (100% overlap)")
+        );
+        let c = fingerprint("Attention Is Not Enough", "", "");
+        assert!(a.matches(&c).is_none());
+        assert_eq!(title_similarity("", "Attention"), 0.0);
+    }
+
+    async fn add_paper(conn: &libsql::Connection, dir: &Path) -> KnownPaper {
+        fs::create_dir_all(dir.join("summary")).unwrap();
+        conn.execute(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+             VALUES (?1, '', '2024-01-01', '')",
+            [dir.display().to_string()],
+        )
+        .await
+        .unwrap();
+        KnownPaper {
+            id: conn.last_insert_rowid() as u32,
+            path: dir.display().to_string(),
+            fingerprint: Fingerprint::default(),
+        }
+    }
+
+    async fn open_library(conn: &libsql::Connection, root: &Path) -> Library {
+        Library::open(conn, &root.join("papr.db"), false, &Config::default())
+            .await
+            .unwrap()
+    }
+
+    async fn count(conn: &libsql::Connection, sql: &str) -> u32 {
+        let mut rows = conn.query(sql, ()).await.unwrap();
+        rows.next().await.unwrap().unwrap().get(0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn merges_notes_attachments_and_progress() {
+        let conn = memory_db().await;
+        let root = tempfile::tempdir().unwrap();
+        let library = open_library(&conn, root.path()).await;
+        let keep = add_paper(&conn, &root.path().join("kept")).await;
+        let drop = add_paper(&conn, &root.path().join("dropped")).await;
+        let other = add_paper(&conn, &root.path().join("other")).await;
+
+        fs::write(root.path().join("dropped/summary/main.typ"), "= Notes").unwrap();
+        fs::create_dir_all(root.path().join("dropped/attachments")).unwrap();
+        fs::write(root.path().join("dropped/attachments/slides.pdf"), "%PDF").unwrap();
+        fs::create_dir_all(root.path().join("kept/attachments")).unwrap();
+        fs::write(root.path().join("kept/attachments/slides.pdf"), "%PDF").unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO attachments (paper_id, kind, file_name, source, date_added)
+                VALUES ({drop}, 'slides', 'slides.pdf', 'slides.pdf', '2024-01-01');
+             UPDATE papers SET status = 'read', rating = 4, url = 'https://a.org' WHERE id = {drop};
+             INSERT INTO tags (name) VALUES ('ml');
+             INSERT INTO paper_tags (paper_id, tag_id) VALUES ({drop}, 1);
+             INSERT INTO collections (name, created_at) VALUES ('thesis', '2024-01-01');
+             INSERT INTO collection_papers (collection_id, paper_id, position)
+                VALUES (1, {other}, 1), (1, {drop}, 2);
+             INSERT INTO note_links (paper_id, target_id, file, line)
+                VALUES ({other}, {drop}, 'main.typ', 3);",
+            drop = drop.id,
+            other = other.id,
+        ))
+        .await
+        .unwrap();
+
+        let trash_dir = root.path().join(".trash");
+        merge_duplicate(&conn, &library, &trash_dir, &keep, &drop)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(root.path().join("kept/summary/from_dropped/main.typ")).unwrap(),
+            "= Notes"
+        );
+        assert!(root.path().join("kept/attachments/slides_2.pdf").exists());
+        assert!(!root.path().join("dropped").exists());
+        assert!(trash_dir.join(format!("{}_dropped", drop.id)).is_dir());
+        let mut rows = conn
+            .query(
+                "SELECT status, rating, url,
+                    (SELECT file_name FROM attachments WHERE paper_id = ?1),
+                    (SELECT position FROM collection_papers WHERE paper_id = ?1),
+                    (SELECT COUNT(*) FROM paper_tags WHERE paper_id = ?1),
+                    (SELECT target_id FROM note_links),
+                    (SELECT deleted_at IS NOT NULL FROM papers WHERE id = ?2)
+                 FROM papers WHERE id = ?1",
+                (keep.id, drop.id),
+            )
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<String>(0).unwrap(), "read");
+        assert_eq!(row.get::<u32>(1).unwrap(), 4);
+        assert_eq!(row.get::<String>(2).unwrap(), "https://a.org");
+        assert_eq!(row.get::<String>(3).unwrap(), "slides_2.pdf");
+        assert_eq!(row.get::<u32>(4).unwrap(), 3);
+        assert_eq!(row.get::<u32>(5).unwrap(), 1);
+        assert_eq!(row.get::<u32>(6).unwrap(), keep.id);
+        assert!(row.get::<bool>(7).unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_merge_changes_nothing() {
+        let conn = memory_db().await;
+        let root = tempfile::tempdir().unwrap();
+        let library = open_library(&conn, root.path()).await;
+        let keep = add_paper(&conn, &root.path().join("kept")).await;
+        let drop = add_paper(&conn, &root.path().join("dropped")).await;
+
+        // The attachment file is missing, so copying it fails after the notes
+        fs::write(root.path().join("dropped/summary/main.typ"), "= Notes").unwrap();
+        conn.execute_batch(&format!(
+            "INSERT INTO attachments (paper_id, kind, file_name, source, date_added)
+                VALUES ({drop}, 'slides', 'slides.pdf', 'slides.pdf', '2024-01-01');
+             INSERT INTO tags (name) VALUES ('ml');
+             INSERT INTO paper_tags (paper_id, tag_id) VALUES ({drop}, 1);",
+            drop = drop.id,
+        ))
+        .await
+        .unwrap();
+
+        let res = merge_duplicate(&conn, &library, &root.path().join(".trash"), &keep, &drop).await;
+        assert!(res.is_err());
+
+        assert!(!root.path().join("kept/summary/from_dropped").exists());
+        assert!(root.path().join("dropped/summary/main.typ").exists());
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM papers WHERE deleted_at IS NULL"
+            )
+            .await,
+            2
+        );
+        assert_eq!(
+            count(
+                &conn,
+                &format!(
+                    "SELECT COUNT(*) FROM paper_tags WHERE paper_id = {}",
+                    keep.id
+                )
+            )
+            .await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn matches_stored_titles_before_directory_names() {
+        let conn = memory_db().await;
+        let root = tempfile::tempdir().unwrap();
+        let library = open_library(&conn, root.path()).await;
+        let named = add_paper(&conn, &root.path().join("vaswani_2017")).await;
+        add_paper(&conn, &root.path().join("attention_is_all_you_need")).await;
+        conn.execute(
+            "UPDATE papers SET title = 'Attention Is All You Need' WHERE id = ?1",
+            [named.id],
+        )
+        .await
+        .unwrap();
+
+        let fingerprint = Fingerprint::new("Attention is all you need", "", "", None).unwrap();
+        let duplicates = find_duplicates(&conn, &library, &fingerprint)
+            .await
+            .unwrap();
+        let ids = duplicates.iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids, [named.id, named.id + 1]);
+    }
+}
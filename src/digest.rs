@@ -0,0 +1,283 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::library::Library;
+use crate::links;
+use crate::naming::{self, slugify};
+use crate::search::{self, PaperFilter};
+use crate::templates::escape;
+use crate::typst;
+
+/// A paper in the digest, with the metadata its header is built from.
+struct DigestPaper {
+    id: u32,
+    key: String,
+    title: String,
+    authors: String,
+    year: String,
+    url: String,
+    citation: String,
+    /// Absolute path of the notes, if the paper has any
+    notes: Option<PathBuf>,
+}
+
+/// Make text safe for a BibTeX field wrapped in braces.
+fn escape_bibtex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '{' | '}' | '\\' => {}
+            '&' | '%' | '$' | '#' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn bibliography(papers: &[DigestPaper]) -> String {
+    let mut bib = String::new();
+    for paper in papers {
+        let _ = writeln!(bib, "@misc{{{},", paper.key);
+        let _ = writeln!(bib, "  title = {{{}}},", escape_bibtex(&paper.title));
+        // Double braces keep free-form author lists from being parsed as names
+        if !paper.authors.is_empty() {
+            let _ = writeln!(bib, "  author = {{{{{}}}}},", escape_bibtex(&paper.authors));
+        }
+        if !paper.year.is_empty() {
+            let _ = writeln!(bib, "  year = {{{}}},", escape_bibtex(&paper.year));
+        }
+        if !paper.url.is_empty() {
+            let _ = writeln!(bib, "  url = {{{}}},", escape_bibtex(&paper.url));
+        }
+        if !paper.citation.is_empty() {
+            let _ = writeln!(bib, "  note = {{{}}},", escape_bibtex(&paper.citation));
+        }
+        bib.push_str("}\n\n");
+    }
+    bib
+}
+
+/// The master document. Each paper gets a heading for the outline, labelled
+/// so that `@papr:<id>` links between notes work, and its notes are included
+/// one heading level down so theirs nest below it.
+fn master_document(heading: &str, papers: &[DigestPaper], bib_name: &str) -> String {
+    let mut doc = String::new();
+    let _ = writeln!(doc, "#set document(title: \"{}\")", escape(heading));
+    doc.push_str(
+        "#set text(font: \"New Computer Modern\")
+#set heading(numbering: \"1.\")
+#show heading: it => [#it #v(0.2em)]\n",
+    );
+    // Links to papers outside the digest have no heading to point at
+    doc.push_str(links::LINK_RULE);
+    doc.push('\n');
+    let _ = writeln!(
+        doc,
+        "#align(center, text(size: 2em)[#\"{}\"])\n#align(center)[{}, {} paper(s)]\n",
+        escape(heading),
+        Local::now().format("%Y-%m-%d"),
+        papers.len()
+    );
+    doc.push_str("#outline(depth: 1)\n\n");
+
+    for paper in papers {
+        let byline = [paper.authors.as_str(), paper.year.as_str()]
+            .iter()
+            .filter(|s| !s.is_empty())
+            .copied()
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = writeln!(
+            doc,
+            "#pagebreak(weak: true)\n= #\"{}\" <papr:{}>",
+            escape(&paper.title),
+            paper.id
+        );
+        let link = if paper.url.is_empty() {
+            String::new()
+        } else {
+            format!(" (#link(\"{}\"))", escape(&paper.url))
+        };
+        if !byline.is_empty() || !link.is_empty() {
+            let _ = writeln!(doc, "#text(fill: gray)[#\"{}\"{}]\n", escape(&byline), link);
+        }
+        match &paper.notes {
+            Some(notes) => {
+                let _ = writeln!(
+                    doc,
+                    "#[\n  #set heading(offset: 1, numbering: none, outlined: false)\n  #include \"{}\"\n]\n",
+                    escape(&notes.to_string_lossy())
+                );
+            }
+            None => doc.push_str("_No notes yet._\n\n"),
+        }
+    }
+
+    let _ = writeln!(
+        doc,
+        "#pagebreak(weak: true)\n#bibliography(\"{}\", title: \"References\", full: true)",
+        escape(bib_name)
+    );
+    doc
+}
+
+pub async fn handle_digest(
+    conn: &libsql::Connection,
+    library: &Library,
+    filter: PaperFilter,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let selection = match (&filter.collection, &filter.tags) {
+        (Some(collection), _) => Some(collection.clone()),
+        (None, Some(tags)) => Some(tags.join(", ")),
+        (None, None) => None,
+    };
+    let heading = match &selection {
+        Some(selection) => format!("Notes: {}", selection),
+        None => "Notes".to_string(),
+    };
+    let papers = search::filter_papers(conn, library, &filter).await?;
+    if papers.is_empty() {
+        anyhow::bail!("No papers match, nothing to put in the digest.");
+    }
+
+    let mut keys: HashMap<String, u32> = HashMap::new();
+    let mut digest_papers = Vec::new();
+    for paper in &papers {
+        let inputs = typst::paper_inputs(conn, library, paper.id)
+            .await?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let value = |key: &str| inputs.get(key).cloned().unwrap_or_default();
+
+        // Keys only need to be unique within the digest
+        let key = value("citation_key");
+        let count = keys.entry(key.clone()).or_default();
+        *count += 1;
+        let key = naming::with_suffix(&key, *count);
+
+        let notes = crate::find_typst_file(&Path::new(&paper.canonical_base_path).join("summary"))
+            .ok()
+            .and_then(|path| fs::canonicalize(path).ok());
+        digest_papers.push(DigestPaper {
+            id: paper.id,
+            key,
+            title: value("title"),
+            authors: value("authors"),
+            year: value("year"),
+            url: value("url"),
+            citation: value("citation"),
+            notes,
+        });
+    }
+
+    let output = match output {
+        Some(output) => output.with_extension("pdf"),
+        None => match slugify(selection.as_deref().unwrap_or_default(), 60) {
+            name if name.is_empty() => PathBuf::from("digest.pdf"),
+            name => PathBuf::from(format!("digest_{}.pdf", name)),
+        },
+    };
+    let master = output.with_extension("typ");
+    let bib = output.with_extension("bib");
+    let bib_name = bib
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("digest.bib");
+
+    fs::write(&bib, bibliography(&digest_papers))
+        .with_context(|| format!("Error writing {}.", bib.display()))?;
+    fs::write(&master, master_document(&heading, &digest_papers, bib_name))
+        .with_context(|| format!("Error writing {}.", master.display()))?;
+
+    // Notes are included by absolute path, so Typst has to be allowed to
+    // read from anywhere
+    println!("Compiling {}...", master.display());
+    let status = Command::new("typst")
+        .arg("compile")
+        .arg("--root")
+        .arg("/")
+        .arg(&master)
+        .arg(&output)
+        .status()
+        .context("Failed to start 'typst compile'. Is it installed?")?;
+    if !status.success() {
+        anyhow::bail!(
+            "Typst could not compile the digest, see {} for the generated document.",
+            master.display()
+        );
+    }
+
+    let missing = digest_papers.iter().filter(|p| p.notes.is_none()).count();
+    println!(
+        "Wrote {} with {} paper(s){}.",
+        output.display(),
+        digest_papers.len(),
+        if missing > 0 {
+            format!(", {} without notes", missing)
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paper(id: u32, title: &str, authors: &str, year: &str) -> DigestPaper {
+        DigestPaper {
+            id,
+            key: format!("key{id}"),
+            title: title.to_string(),
+            authors: authors.to_string(),
+            year: year.to_string(),
+            url: String::new(),
+            citation: String::new(),
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn escapes_bibtex_specials() {
+        assert_eq!(
+            escape_bibtex("{Fast} 100% R&D_x #1 $5\\\nnext"),
+            "Fast 100\\% R\\&D\\_x \\#1 \\$5 next"
+        );
+    }
+
+    #[test]
+    fn bibliography_skips_empty_fields() {
+        let papers = [
+            paper(1, "Deep & Wide", "A. Author and B. Author", "2020"),
+            paper(2, "Untitled", "", ""),
+        ];
+        assert_eq!(
+            bibliography(&papers),
+            "@misc{key1,\n  title = {Deep \\& Wide},\n  author = {{A. Author and B. Author}},\n  \
+             year = {2020},\n}\n\n@misc{key2,\n  title = {Untitled},\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn master_document_labels_papers_and_includes_notes() {
+        let mut with_notes = paper(3, "Noted", "", "2021");
+        with_notes.notes = Some(PathBuf::from("/lib/noted/notes.typ"));
+        let doc = master_document("ml", &[with_notes, paper(4, "Bare", "", "")], "refs.bib");
+        assert!(doc.contains("= #\"Noted\" <papr:3>"));
+        assert!(doc.contains("#text(fill: gray)[#\"2021\"]"));
+        assert!(doc.contains("#include \"/lib/noted/notes.typ\""));
+        assert!(doc.contains("= #\"Bare\" <papr:4>\n_No notes yet._"));
+        assert!(doc.contains("#bibliography(\"refs.bib\""));
+    }
+}
@@ -0,0 +1,355 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::delete_paper_links;
+use crate::library::Library;
+
+/// How deep below the library root to look for untracked paper directories.
+const MAX_SCAN_DEPTH: usize = 3;
+
+enum Problem {
+    MissingDirectory { id: u32, path: String },
+    MissingPdf { path: String },
+    UnreadablePdf { path: String, reason: String },
+    UntrackedDirectory { path: PathBuf },
+    DanglingPaperTags { count: u32 },
+    DuplicatePaperTags { count: u32 },
+    OrphanedTags { names: Vec<String> },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingDirectory { id, path } => {
+                write!(f, "Paper {} points at a missing directory: {}", id, path)
+            }
+            Self::MissingPdf { path } => write!(f, "Missing paper.pdf in {}", path),
+            Self::UnreadablePdf { path, reason } => {
+                write!(f, "Unreadable paper.pdf in {}: {}", path, reason)
+            }
+            Self::UntrackedDirectory { path } => {
+                write!(f, "Paper directory not in the database: {}", path.display())
+            }
+            Self::DanglingPaperTags { count } => {
+                write!(
+                    f,
+                    "{} paper_tags row(s) point at missing papers or tags",
+                    count
+                )
+            }
+            Self::DuplicatePaperTags { count } => {
+                write!(f, "{} duplicate paper_tags row(s)", count)
+            }
+            Self::OrphanedTags { names } => {
+                write!(f, "Tags not used by any paper: {}", names.join(", "))
+            }
+        }
+    }
+}
+
+impl Problem {
+    /// What `--fix` does about this problem, or how to fix it by hand.
+    fn remedy(&self) -> &'static str {
+        match self {
+            Self::MissingDirectory { .. } => "--fix removes the DB entry",
+            Self::MissingPdf { .. } | Self::UnreadablePdf { .. } => {
+                "run `papr refetch` to download the PDF again"
+            }
+            Self::UntrackedDirectory { .. } => "run `papr scan` to add it to the database",
+            Self::DanglingPaperTags { .. } | Self::DuplicatePaperTags { .. } => {
+                "--fix deletes the extra rows"
+            }
+            Self::OrphanedTags { .. } => "--fix deletes the tags",
+        }
+    }
+
+    async fn fix(&self, conn: &libsql::Connection) -> Result<bool> {
+        match self {
+            Self::MissingDirectory { id, .. } => {
+                delete_paper_links(conn, *id).await?;
+                conn.execute("DELETE FROM papers WHERE id = ?1", [*id])
+                    .await?;
+            }
+            Self::DanglingPaperTags { .. } => {
+                conn.execute(
+                    "DELETE FROM paper_tags
+                     WHERE paper_id NOT IN (SELECT id FROM papers)
+                        OR tag_id NOT IN (SELECT id FROM tags)",
+                    (),
+                )
+                .await?;
+            }
+            Self::DuplicatePaperTags { .. } => {
+                conn.execute(
+                    "DELETE FROM paper_tags WHERE rowid NOT IN
+                     (SELECT MIN(rowid) FROM paper_tags GROUP BY paper_id, tag_id)",
+                    (),
+                )
+                .await?;
+            }
+            Self::OrphanedTags { .. } => {
+                conn.execute(
+                    "DELETE FROM tags WHERE id NOT IN (SELECT DISTINCT tag_id FROM paper_tags)",
+                    (),
+                )
+                .await?;
+            }
+            Self::MissingPdf { .. }
+            | Self::UnreadablePdf { .. }
+            | Self::UntrackedDirectory { .. } => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/// Check that a file is a PDF that `pdf_extract` can read.
+pub(crate) fn check_pdf(pdf_path: &Path) -> std::result::Result<(), String> {
+    let bytes = fs::read(pdf_path).map_err(|e| e.to_string())?;
+    if !bytes.starts_with(b"%PDF") {
+        return Err("not a PDF file".to_string());
+    }
+
+    // `pdf_extract` panics on some malformed files
+    match std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes)) {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("PDF parser crashed".to_string()),
+    }
+}
+
+/// Whether a directory looks like one created by `papr add`.
+pub(crate) fn looks_like_paper_dir(dir: &Path) -> bool {
+    dir.join("paper.pdf").is_file() || dir.join("summary").join("main.typ").is_file()
+}
+
+/// Walk `dir` looking for directories that `is_candidate` accepts and that are
+/// not in `known`. Hidden directories and known paper directories are not
+/// descended into.
+pub(crate) fn find_untracked_dirs(
+    dir: &Path,
+    known: &HashSet<PathBuf>,
+    depth: usize,
+    is_candidate: &dyn Fn(&Path) -> bool,
+    found: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !entry.file_type()?.is_dir() || hidden || known.contains(&path) {
+            continue;
+        }
+
+        if is_candidate(&path) {
+            found.push(path);
+        } else if depth > 1 {
+            find_untracked_dirs(&path, known, depth - 1, is_candidate, found)?;
+        }
+    }
+    Ok(())
+}
+
+/// All paper directories the database knows about, including trashed ones.
+pub(crate) async fn get_known_dirs(
+    conn: &libsql::Connection,
+    library: &Library,
+) -> Result<HashSet<PathBuf>> {
+    let mut rows = conn
+        .query("SELECT canonical_base_path FROM papers", ())
+        .await?;
+    let mut known = HashSet::new();
+    while let Some(row) = rows.next().await? {
+        known.insert(library.resolve(&row.get::<String>(0)?));
+    }
+    Ok(known)
+}
+
+async fn count(conn: &libsql::Connection, sql: &str) -> Result<u32> {
+    Ok(conn
+        .query(sql, ())
+        .await?
+        .next()
+        .await?
+        .map(|row| row.get(0))
+        .transpose()?
+        .unwrap_or(0))
+}
+
+async fn find_problems(conn: &libsql::Connection, library: &Library) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+
+    let mut rows = conn
+        .query(
+            "SELECT id, canonical_base_path FROM papers WHERE deleted_at IS NULL",
+            (),
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        let id: u32 = row.get(0)?;
+        let base_path = library.resolve(&row.get::<String>(1)?);
+        let path = base_path.display().to_string();
+
+        if !base_path.is_dir() {
+            problems.push(Problem::MissingDirectory { id, path });
+            continue;
+        }
+
+        let pdf_path = base_path.join("paper.pdf");
+        if !pdf_path.exists() {
+            problems.push(Problem::MissingPdf { path });
+        } else if let Err(reason) = check_pdf(&pdf_path) {
+            problems.push(Problem::UnreadablePdf { path, reason });
+        }
+    }
+
+    let known = get_known_dirs(conn, library).await?;
+    let mut untracked = Vec::new();
+    find_untracked_dirs(
+        library.root(),
+        &known,
+        MAX_SCAN_DEPTH,
+        &looks_like_paper_dir,
+        &mut untracked,
+    )?;
+    problems.extend(
+        untracked
+            .into_iter()
+            .map(|path| Problem::UntrackedDirectory { path }),
+    );
+
+    let dangling = count(
+        conn,
+        "SELECT COUNT(*) FROM paper_tags
+         WHERE paper_id NOT IN (SELECT id FROM papers)
+            OR tag_id NOT IN (SELECT id FROM tags)",
+    )
+    .await?;
+    if dangling > 0 {
+        problems.push(Problem::DanglingPaperTags { count: dangling });
+    }
+
+    let duplicates = count(
+        conn,
+        "SELECT COUNT(*) - COUNT(DISTINCT paper_id || ':' || tag_id) FROM paper_tags",
+    )
+    .await?;
+    if duplicates > 0 {
+        problems.push(Problem::DuplicatePaperTags { count: duplicates });
+    }
+
+    let mut rows = conn
+        .query(
+            "SELECT name FROM tags WHERE id NOT IN (SELECT DISTINCT tag_id FROM paper_tags)",
+            (),
+        )
+        .await?;
+    let mut names = Vec::new();
+    while let Some(row) = rows.next().await? {
+        names.push(row.get::<Option<String>>(0)?.unwrap_or_default());
+    }
+    if !names.is_empty() {
+        problems.push(Problem::OrphanedTags { names });
+    }
+
+    Ok(problems)
+}
+
+pub async fn handle_doctor(conn: &libsql::Connection, library: &Library, fix: bool) -> Result<()> {
+    let problems = find_problems(conn, library).await?;
+    if problems.is_empty() {
+        println!("No problems found.");
+        return Ok(());
+    }
+
+    let mut fixed = 0;
+    for problem in &problems {
+        if fix && problem.fix(conn).await? {
+            println!("Fixed: {}", problem);
+            fixed += 1;
+        } else {
+            println!("{}\n  ({})", problem, problem.remedy());
+        }
+    }
+
+    println!("\nFound {} problem(s), fixed {}.", problems.len(), fixed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::tests::memory_db;
+
+    #[test]
+    fn rejects_files_that_are_not_pdfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("paper.pdf");
+        fs::write(&path, "<html>Access denied</html>").unwrap();
+        assert_eq!(check_pdf(&path), Err("not a PDF file".to_string()));
+        assert!(check_pdf(&dir.path().join("missing.pdf")).is_err());
+    }
+
+    #[tokio::test]
+    async fn finds_and_fixes_problems() {
+        let conn = memory_db().await;
+        let root = tempfile::tempdir().unwrap();
+        let library = Library::open(
+            &conn,
+            &root.path().join("papr.db"),
+            false,
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        fs::create_dir_all(root.path().join("no_pdf")).unwrap();
+        fs::create_dir_all(root.path().join("ml/stray/summary")).unwrap();
+        fs::write(root.path().join("ml/stray/summary/main.typ"), "").unwrap();
+        fs::create_dir_all(root.path().join(".trash/old")).unwrap();
+        fs::write(root.path().join(".trash/old/paper.pdf"), "%PDF").unwrap();
+        conn.execute_batch(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+                VALUES ('gone', '', '2024-01-01', ''), ('no_pdf', '', '2024-01-01', '');
+             INSERT INTO tags (name) VALUES ('used'), ('unused');
+             INSERT INTO paper_tags (paper_id, tag_id) VALUES (2, 1);
+             PRAGMA foreign_keys = OFF;
+             INSERT INTO paper_tags (paper_id, tag_id) VALUES (2, 9);
+             PRAGMA foreign_keys = ON;",
+        )
+        .await
+        .unwrap();
+
+        let problems = find_problems(&conn, &library).await.unwrap();
+        let messages = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                format!(
+                    "Paper 1 points at a missing directory: {}",
+                    root.path().join("gone").display()
+                ),
+                format!(
+                    "Missing paper.pdf in {}",
+                    root.path().join("no_pdf").display()
+                ),
+                format!(
+                    "Paper directory not in the database: {}",
+                    root.path().join("ml/stray").display()
+                ),
+                "1 paper_tags row(s) point at missing papers or tags".to_string(),
+                "Tags not used by any paper: unused".to_string(),
+            ]
+        );
+
+        for problem in &problems {
+            problem.fix(&conn).await.unwrap();
+        }
+        let remaining = find_problems(&conn, &library).await.unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert!(matches!(remaining[0], Problem::MissingPdf { .. }));
+        assert!(matches!(remaining[1], Problem::UntrackedDirectory { .. }));
+    }
+}
@@ -0,0 +1,636 @@
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{CONTENT_TYPE, ETAG, HeaderMap, IF_RANGE, LAST_MODIFIED, RANGE};
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::DownloadConfig;
+
+/// How many landing pages to follow before giving up on finding a PDF.
+const MAX_LANDING_PAGE_HOPS: usize = 2;
+
+pub fn is_pdf(content: &[u8]) -> bool {
+    content.starts_with(b"%PDF")
+}
+
+pub fn is_pdf_file(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 4];
+    let mut file = fs::File::open(path).with_context(|| format!("Error reading {:?}.", path))?;
+    let read = file.read(&mut magic)?;
+    Ok(is_pdf(&magic[..read]))
+}
+
+enum FetchError {
+    /// Network hiccups and server errors, worth another attempt
+    Retryable(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+struct FetchInfo {
+    content_type: String,
+    final_url: reqwest::Url,
+}
+
+/// A finished download in the temporary download directory. The file is
+/// removed when this is dropped, so cancelled or failed commands leave
+/// nothing behind.
+pub struct Download {
+    path: PathBuf,
+}
+
+impl Download {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for Download {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// What identifies the version of a file on the server, sent as `If-Range`
+/// so a resumed download is not stitched together from two versions.
+fn validator(headers: &HeaderMap) -> Option<String> {
+    // Weak ETags are not allowed in If-Range
+    let etag = headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"));
+    etag.or_else(|| headers.get(LAST_MODIFIED).and_then(|v| v.to_str().ok()))
+        .map(str::to_string)
+}
+
+/// Prints a single, continuously updated progress line to stderr.
+struct Progress {
+    done: u64,
+    total: Option<u64>,
+    last_percent: Option<u64>,
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+impl Progress {
+    fn new(done: u64, total: Option<u64>) -> Self {
+        Self {
+            done,
+            total,
+            last_percent: None,
+        }
+    }
+
+    fn advance(&mut self, bytes: usize) {
+        self.done += bytes as u64;
+        match self.total {
+            Some(total) if total > 0 => {
+                let percent = self.done * 100 / total;
+                if self.last_percent != Some(percent) {
+                    self.last_percent = Some(percent);
+                    eprint!(
+                        "\r  {:.1} / {:.1} MiB ({}%)",
+                        mib(self.done),
+                        mib(total),
+                        percent
+                    );
+                }
+            }
+            _ => eprint!("\r  {:.1} MiB", mib(self.done)),
+        }
+    }
+
+    fn finish(&self) {
+        eprintln!();
+    }
+}
+
+/// HTTP client shared by every command that downloads files.
+///
+/// Downloads are streamed to a partial file in the temporary directory,
+/// keyed by URL, so a download interrupted by a network error resumes where
+/// it left off on the next attempt.
+pub struct Downloader {
+    client: reqwest::Client,
+    retries: u32,
+    backoff: Duration,
+    cache_dir: PathBuf,
+}
+
+impl Downloader {
+    pub fn new(config: &DownloadConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(config.user_agent.as_str())
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .read_timeout(Duration::from_secs(config.read_timeout_secs))
+            .build()
+            .context("Error creating HTTP client.")?;
+
+        Ok(Self {
+            client,
+            retries: config.retries,
+            backoff: Duration::from_millis(config.backoff_ms),
+            cache_dir: std::env::temp_dir().join("papr-downloads"),
+        })
+    }
+
+    fn partial_path(&self, url: &str) -> PathBuf {
+        let hash = Sha256::digest(url.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.cache_dir.join(format!("{}.part", hash))
+    }
+
+    /// One attempt at downloading `url` into `dest`, continuing from what an
+    /// earlier attempt left there. `version` is the validator of the file
+    /// being downloaded, once known.
+    async fn try_fetch(
+        &self,
+        url: &str,
+        dest: &Path,
+        version: &mut Option<String>,
+    ) -> Result<FetchInfo, FetchError> {
+        let resume_from = fs::metadata(dest).map(|m| m.len()).unwrap_or(0);
+
+        let mut request = self.client.get(url);
+        if resume_from > 0 {
+            request = request.header(RANGE, format!("bytes={}-", resume_from));
+            if let Some(version) = version.as_deref() {
+                request = request.header(IF_RANGE, version);
+            }
+        }
+        // Only network trouble is worth retrying, not e.g. a bad URL or redirect loop
+        let mut response = request.send().await.map_err(|e| {
+            let retryable = e.is_connect() || e.is_timeout();
+            let e = anyhow::Error::new(e).context(format!("Error downloading {}.", url));
+            if retryable {
+                FetchError::Retryable(e)
+            } else {
+                FetchError::Fatal(e)
+            }
+        })?;
+
+        let status = response.status();
+        let info = FetchInfo {
+            content_type: response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_lowercase(),
+            final_url: response.url().clone(),
+        };
+
+        // The file on the server no longer matches what was downloaded so far
+        if status == StatusCode::RANGE_NOT_SATISFIABLE && resume_from > 0 {
+            let _ = fs::remove_file(dest);
+            return Err(FetchError::Retryable(anyhow::anyhow!(
+                "Server could not resume {}, starting over.",
+                url
+            )));
+        }
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(FetchError::Retryable(anyhow::anyhow!(
+                "Server returned {} for {}.",
+                status,
+                url
+            )));
+        }
+        if !status.is_success() {
+            return Err(FetchError::Fatal(anyhow::anyhow!(
+                "Server returned {} for {}.",
+                status,
+                url
+            )));
+        }
+
+        // Servers that ignore the Range header, or have a newer version of
+        // the file, send the whole file again
+        let append = status == StatusCode::PARTIAL_CONTENT && resume_from > 0;
+        if !append {
+            *version = validator(response.headers());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(dest)
+            .map_err(|e| FetchError::Fatal(e.into()))?;
+
+        let done = if append { resume_from } else { 0 };
+        if append {
+            println!("Resuming download at {:.1} MiB...", mib(done));
+        }
+        let mut progress = Progress::new(done, response.content_length().map(|len| len + done));
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    progress.finish();
+                    return Err(FetchError::Retryable(
+                        anyhow::Error::new(e)
+                            .context(format!("Download of {} was interrupted.", url)),
+                    ));
+                }
+            };
+            file.write_all(&chunk)
+                .map_err(|e| FetchError::Fatal(e.into()))?;
+            progress.advance(chunk.len());
+        }
+        progress.finish();
+
+        Ok(info)
+    }
+
+    /// Download `url` into its partial file, retrying with exponential backoff.
+    async fn fetch(&self, url: &str) -> Result<(Download, FetchInfo)> {
+        fs::create_dir_all(&self.cache_dir).context("Error creating download directory.")?;
+        let download = Download {
+            path: self.partial_path(url),
+        };
+        // Whatever an earlier run left behind may be of another version
+        let _ = fs::remove_file(download.path());
+
+        let mut version = None;
+        let mut attempt = 0;
+        loop {
+            match self.try_fetch(url, download.path(), &mut version).await {
+                Ok(info) => return Ok((download, info)),
+                Err(FetchError::Retryable(e)) if attempt < self.retries => {
+                    let delay = self.backoff * 2u32.pow(attempt);
+                    eprintln!("{:#}\nRetrying in {:.1}s...", e, delay.as_secs_f64());
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(FetchError::Retryable(e)) | Err(FetchError::Fatal(e)) => return Err(e),
+            }
+        }
+    }
+
+    /// Download any file into the temporary download directory.
+    pub async fn download(&self, url: &str) -> Result<Download> {
+        println!("Downloading {}...", url);
+        Ok(self.fetch(url).await?.0)
+    }
+
+    /// Fetch a small text response, such as an API query, into memory.
+    pub async fn get_text(&self, url: &str) -> Result<String> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Error fetching {}.", url))?;
+        response
+            .text()
+            .await
+            .with_context(|| format!("Error reading response from {}.", url))
+    }
+
+    /// Download a PDF, following `citation_pdf_url` links when the URL points
+    /// at an HTML landing page (e.g. an arXiv abstract page). Fails without
+    /// returning anything if no PDF can be found.
+    pub async fn download_pdf(&self, url: &str) -> Result<Download> {
+        println!("Downloading PDF...");
+        let mut url = url.to_string();
+
+        for _ in 0..=MAX_LANDING_PAGE_HOPS {
+            let (download, info) = self.fetch(&url).await?;
+            if is_pdf_file(download.path())? {
+                return Ok(download);
+            }
+            let content = fs::read(download.path())?;
+            drop(download);
+
+            let looks_like_html = info.content_type.contains("html")
+                || String::from_utf8_lossy(&content[..content.len().min(512)])
+                    .to_lowercase()
+                    .contains("<html");
+            if !looks_like_html {
+                anyhow::bail!(
+                    "{} did not return a PDF (content type '{}').",
+                    url,
+                    info.content_type
+                );
+            }
+
+            let html = String::from_utf8_lossy(&content);
+            let Some(pdf_url) = find_meta_content(&html, "citation_pdf_url") else {
+                anyhow::bail!(
+                    "{} returned an HTML page without a PDF link. Use the direct PDF URL instead.",
+                    url
+                );
+            };
+            let pdf_url = info
+                .final_url
+                .join(&pdf_url)
+                .with_context(|| format!("Invalid PDF link '{}' on {}.", pdf_url, url))?;
+            println!("Found PDF link on landing page: {}", pdf_url);
+            url = pdf_url.to_string();
+        }
+
+        anyhow::bail!("Gave up following landing pages at {}.", url)
+    }
+}
+
+/// Value of attribute `attr` in `tag`, quoted or not. `tag_lower` is `tag`
+/// lowercased, for finding the attribute name.
+fn attribute<'a>(tag: &'a str, tag_lower: &str, attr: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", attr);
+    let mut offset = 0;
+    while let Some(found) = tag_lower[offset..].find(&pattern) {
+        let start = offset + found;
+        offset = start + pattern.len();
+        // Skip longer attribute names ending in `attr`, such as `data-name=`
+        if !tag_lower[..start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+        let quote = tag[offset..].chars().next()?;
+        return if quote == '"' || quote == '\'' {
+            let rest = &tag[offset + 1..];
+            Some(&rest[..rest.find(quote)?])
+        } else {
+            tag[offset..].split_whitespace().next()
+        };
+    }
+    None
+}
+
+/// Value of the `content` attribute of the first `<meta>` tag whose `name`
+/// or `property` is `name`.
+fn find_meta_content(html: &str, name: &str) -> Option<String> {
+    // ASCII lowercasing keeps byte offsets valid for slicing `html`
+    let lower = html.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(start) = lower[offset..].find("<meta") {
+        let start = offset + start;
+        let end = start + lower[start..].find('>')?;
+        let tag = &html[start..end];
+        let tag_lower = &lower[start..end];
+        offset = end;
+
+        let named = ["name", "property"].iter().any(|attr| {
+            attribute(tag, tag_lower, attr).is_some_and(|value| value.eq_ignore_ascii_case(name))
+        });
+        if !named {
+            continue;
+        }
+        let Some(value) = attribute(tag, tag_lower, "content") else {
+            continue;
+        };
+        return Some(value.replace("&amp;", "&"));
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const PDF: &[u8] = b"%PDF-1.4 test document";
+
+    /// A canned response, optionally cut off after `sent` body bytes to
+    /// simulate a dropped connection.
+    pub(crate) struct Reply {
+        status: &'static str,
+        headers: Vec<&'static str>,
+        body: &'static [u8],
+        sent: Option<usize>,
+    }
+
+    pub(crate) fn reply(status: &'static str, body: &'static [u8]) -> Reply {
+        Reply {
+            status,
+            headers: Vec::new(),
+            body,
+            sent: None,
+        }
+    }
+
+    /// Serve `replies` in order, one per connection. Returns the base URL
+    /// and the lowercased requests received so far.
+    pub(crate) async fn serve(replies: Vec<Reply>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/paper.pdf", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for reply in replies {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_lowercase());
+
+                let mut head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    reply.status,
+                    reply.body.len()
+                );
+                for header in &reply.headers {
+                    head.push_str(header);
+                    head.push_str("\r\n");
+                }
+                head.push_str("\r\n");
+                let body = &reply.body[..reply.sent.unwrap_or(reply.body.len())];
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(body).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (url, requests)
+    }
+
+    pub(crate) fn downloader() -> Downloader {
+        Downloader::new(&DownloadConfig {
+            connect_timeout_secs: 5,
+            read_timeout_secs: 5,
+            retries: 3,
+            backoff_ms: 1,
+            user_agent: "papr-test/1.0".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_and_rate_limits() {
+        let (url, requests) = serve(vec![
+            reply("503 Service Unavailable", b""),
+            reply("429 Too Many Requests", b""),
+            reply("200 OK", PDF),
+        ])
+        .await;
+        let download = downloader().download_pdf(&url).await.unwrap();
+        assert_eq!(fs::read(download.path()).unwrap(), PDF);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (url, requests) = serve(vec![reply("404 Not Found", b""), reply("200 OK", PDF)]).await;
+        assert!(downloader().download_pdf(&url).await.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn redirect_loops_are_not_retried() {
+        let mut replies = (0..11)
+            .map(|_| Reply {
+                headers: vec!["Location: /paper.pdf"],
+                ..reply("302 Found", b"")
+            })
+            .collect::<Vec<_>>();
+        replies.push(reply("200 OK", PDF));
+        let (url, requests) = serve(replies).await;
+        assert!(downloader().download_pdf(&url).await.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 11);
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_download() {
+        let (url, requests) = serve(vec![
+            Reply {
+                headers: vec!["ETag: \"v1\""],
+                sent: Some(8),
+                ..reply("200 OK", PDF)
+            },
+            reply("206 Partial Content", &PDF[8..]),
+        ])
+        .await;
+        let download = downloader().download_pdf(&url).await.unwrap();
+        assert_eq!(fs::read(download.path()).unwrap(), PDF);
+
+        let requests = requests.lock().unwrap();
+        assert!(requests[1].contains("range: bytes=8-"));
+        assert!(requests[1].contains("if-range: \"v1\""));
+    }
+
+    #[tokio::test]
+    async fn restarts_when_range_is_ignored() {
+        let (url, _) = serve(vec![
+            Reply {
+                sent: Some(8),
+                ..reply("200 OK", PDF)
+            },
+            reply("200 OK", PDF),
+        ])
+        .await;
+        let download = downloader().download_pdf(&url).await.unwrap();
+        assert_eq!(fs::read(download.path()).unwrap(), PDF);
+    }
+
+    #[tokio::test]
+    async fn starts_over_when_range_is_not_satisfiable() {
+        let (url, requests) = serve(vec![
+            Reply {
+                sent: Some(8),
+                ..reply("200 OK", PDF)
+            },
+            reply("416 Range Not Satisfiable", b""),
+            reply("200 OK", PDF),
+        ])
+        .await;
+        let download = downloader().download_pdf(&url).await.unwrap();
+        assert_eq!(fs::read(download.path()).unwrap(), PDF);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(!requests[2].contains("range:"));
+    }
+
+    #[tokio::test]
+    async fn ignores_partial_file_from_earlier_run() {
+        let (url, requests) = serve(vec![reply("200 OK", PDF)]).await;
+        let downloader = downloader();
+        fs::create_dir_all(&downloader.cache_dir).unwrap();
+        fs::write(downloader.partial_path(&url), b"%PDF-old").unwrap();
+
+        let download = downloader.download_pdf(&url).await.unwrap();
+        assert_eq!(fs::read(download.path()).unwrap(), PDF);
+        assert!(!requests.lock().unwrap()[0].contains("range:"));
+    }
+
+    #[tokio::test]
+    async fn sends_user_agent() {
+        let (url, requests) = serve(vec![reply("200 OK", PDF)]).await;
+        downloader().download_pdf(&url).await.unwrap();
+        assert!(requests.lock().unwrap()[0].contains("user-agent: papr-test/1.0"));
+    }
+
+    #[tokio::test]
+    async fn download_is_removed_when_dropped() {
+        let (url, _) = serve(vec![reply("200 OK", PDF)]).await;
+        let download = downloader().download(&url).await.unwrap();
+        let path = download.path().to_path_buf();
+        assert!(path.exists());
+        drop(download);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn finds_meta_content_after_non_ascii_text() {
+        // `İ` lowercases to a longer string with full Unicode lowercasing
+        let html = "<html><head><title>İİİ Über</title>\
+            <meta name=\"citation_pdf_url\" content=\"https://example.org/a.pdf?x=1&amp;y=2\">\
+            </head></html>";
+        assert_eq!(
+            find_meta_content(html, "citation_pdf_url").as_deref(),
+            Some("https://example.org/a.pdf?x=1&y=2")
+        );
+    }
+
+    #[test]
+    fn finds_unquoted_meta_content() {
+        let html = "<META NAME=citation_pdf_url CONTENT=/pdf/1.pdf>";
+        assert_eq!(
+            find_meta_content(html, "citation_pdf_url").as_deref(),
+            Some("/pdf/1.pdf")
+        );
+        assert_eq!(find_meta_content(html, "citation_doi"), None);
+    }
+
+    #[test]
+    fn skips_meta_tags_without_content_or_other_names() {
+        let html = "<meta name=\"citation_pdf_url\">\
+            <meta name=\"citation_pdf_url_old\" content=\"/old.pdf\">\
+            <meta data-name=\"citation_pdf_url\" content=\"/data.pdf\">\
+            <meta property='citation_pdf_url' content='/paper.pdf'>";
+        assert_eq!(
+            find_meta_content(html, "citation_pdf_url").as_deref(),
+            Some("/paper.pdf")
+        );
+    }
+
+    #[test]
+    fn recognises_pdf_magic() {
+        assert!(is_pdf(b"%PDF-1.7\n"));
+        assert!(!is_pdf(b"<html>"));
+        assert!(!is_pdf(b""));
+    }
+}
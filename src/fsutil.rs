@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Recursively copy the contents of `from` into `to`, creating `to` if needed.
+pub fn copy_dir_all(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to).with_context(|| format!("Error creating directory {:?}.", to))?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("Error copying {:?}.", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether the not yet existing path `to` would be inside `from`, following
+/// symlinks.
+fn is_inside(from: &Path, to: &Path) -> Result<bool> {
+    let from = fs::canonicalize(from).with_context(|| format!("Error resolving {:?}.", from))?;
+    for ancestor in to.ancestors().skip(1) {
+        let ancestor = if ancestor.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            ancestor
+        };
+        if ancestor.exists() {
+            return Ok(fs::canonicalize(ancestor)?.starts_with(&from));
+        }
+    }
+    Ok(false)
+}
+
+/// Move a directory, falling back to copy-then-delete when a plain rename
+/// is not possible (e.g. when moving across filesystems).
+pub fn move_dir(from: &Path, to: &Path) -> Result<()> {
+    if to.exists() {
+        anyhow::bail!("Refusing to overwrite existing path {:?}.", to);
+    }
+    // Copying a directory into itself would never finish
+    if is_inside(from, to)? {
+        anyhow::bail!("Cannot move {:?} into itself.", from);
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if let Err(e) = copy_dir_all(from, to) {
+        // Leave the source untouched and clean up the partial copy
+        let _ = fs::remove_dir_all(to);
+        return Err(e.context(format!("Error moving {:?} to {:?}.", from, to)));
+    }
+    fs::remove_dir_all(from).with_context(|| format!("Error removing {:?} after copy.", from))
+}
+
+/// A directory that is built at a hidden sibling path and only moved to its
+/// final location once everything else has succeeded.
+///
+/// Dropping a `StagedDir` without calling `finish` rolls the filesystem back:
+/// the staging directory is removed and any directory it replaced is put back.
+pub struct StagedDir {
+    staging: PathBuf,
+    target: PathBuf,
+    backup: PathBuf,
+    swapped: bool,
+    finished: bool,
+}
+
+impl StagedDir {
+    pub fn new(target: &Path) -> Result<Self> {
+        let name = target
+            .file_name()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid directory name {:?}.", target))?;
+        let parent = target.parent().unwrap_or(Path::new("."));
+        let staging = parent.join(format!(".{}.papr-staging", name));
+        let backup = parent.join(format!(".{}.papr-backup", name));
+
+        // Left over from an interrupted run: the staging directory is never
+        // referenced by the DB, while a backup is the only copy of the old
+        // directory if the run died halfway through `swap_in`
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        if backup.exists() {
+            if target.exists() {
+                fs::remove_dir_all(&backup)?;
+            } else {
+                fs::rename(&backup, target)?;
+            }
+        }
+        fs::create_dir_all(&staging)
+            .with_context(|| format!("Error creating staging directory {:?}.", staging))?;
+
+        Ok(Self {
+            staging,
+            target: target.to_path_buf(),
+            backup,
+            swapped: false,
+            finished: false,
+        })
+    }
+
+    /// Where files should be written before the directory is swapped in.
+    pub fn path(&self) -> &Path {
+        &self.staging
+    }
+
+    /// Move the staged directory to the target path, keeping any existing
+    /// directory at the target as a backup until `finish` is called.
+    pub fn swap_in(&mut self) -> Result<()> {
+        if self.target.exists() {
+            fs::rename(&self.target, &self.backup)
+                .with_context(|| format!("Error backing up {:?}.", self.target))?;
+        }
+        if let Err(e) = fs::rename(&self.staging, &self.target) {
+            let _ = fs::rename(&self.backup, &self.target);
+            return Err(e).with_context(|| format!("Error moving {:?} into place.", self.target));
+        }
+        self.swapped = true;
+        Ok(())
+    }
+
+    /// Keep the new directory and discard the backup of the old one.
+    pub fn finish(mut self) -> Result<()> {
+        self.finished = true;
+        if self.backup.exists() {
+            fs::remove_dir_all(&self.backup)
+                .with_context(|| format!("Error removing backup {:?}.", self.backup))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagedDir {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if self.swapped {
+            let _ = fs::remove_dir_all(&self.target);
+            if self.backup.exists() {
+                let _ = fs::rename(&self.backup, &self.target);
+            }
+        } else {
+            let _ = fs::remove_dir_all(&self.staging);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_to_move_a_directory_into_itself() {
+        let root = tempfile::tempdir().unwrap();
+        let paper = root.path().join("paper");
+        fs::create_dir_all(paper.join("summary")).unwrap();
+
+        assert!(move_dir(&paper, &paper.join("summary/nested")).is_err());
+        assert!(move_dir(&paper, &paper.join("new/deeper")).is_err());
+        assert!(!paper.join("new").exists());
+
+        move_dir(&paper, &root.path().join("moved")).unwrap();
+        assert!(root.path().join("moved/summary").is_dir());
+    }
+
+    #[test]
+    fn staged_dir_rolls_back_unless_finished() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("paper");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("old"), "").unwrap();
+
+        let mut staged = StagedDir::new(&target).unwrap();
+        fs::write(staged.path().join("new"), "").unwrap();
+        staged.swap_in().unwrap();
+        drop(staged);
+        assert!(target.join("old").exists());
+        assert!(!target.join("new").exists());
+
+        let mut staged = StagedDir::new(&target).unwrap();
+        fs::write(staged.path().join("new"), "").unwrap();
+        staged.swap_in().unwrap();
+        staged.finish().unwrap();
+        assert!(!target.join("old").exists());
+        assert!(target.join("new").exists());
+        assert_eq!(fs::read_dir(root.path()).unwrap().count(), 1);
+    }
+}
@@ -1,15 +1,70 @@
+mod arxiv;
+mod attachments;
+mod browse;
+mod collections;
+mod config;
+mod db;
+mod dedupe;
+mod digest;
+mod doctor;
+mod download;
+mod fsutil;
+mod library;
+mod links;
+mod naming;
+mod pdfmeta;
+mod rating;
+mod scan;
 mod search;
+mod sessions;
+mod status;
+mod templates;
+mod trash;
+mod typst;
+mod viewer;
 
 use anyhow::{Context, Result};
 use chrono::Local;
 use directories::ProjectDirs;
-use inquire::{Confirm, Editor, MultiSelect, Select, Text};
+use inquire::validator::ValueRequiredValidator;
+use inquire::{Editor, MultiSelect, Select, Text};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{fmt, fs};
 
+use crate::browse::Hit;
+use crate::config::ViewerConfig;
+use crate::dedupe::{DuplicateAction, Fingerprint};
+use crate::download::{Download, is_pdf_file};
+use crate::fsutil::StagedDir;
+use crate::naming::NameParts;
+use crate::pdfmeta::{PdfMetadata, read_pdf_metadata};
 use crate::search::PaperMatch;
 
+pub use arxiv::handle_outdated;
+pub use attachments::{AttachmentKind, handle_attach};
+pub use collections::{
+    handle_collection_add, handle_collection_create, handle_collection_delete,
+    handle_collection_list, handle_collection_remove, handle_collection_reorder,
+    handle_collection_show,
+};
+pub use config::Config;
+pub use db::init_schema;
+pub use dedupe::handle_dedupe;
+pub use digest::handle_digest;
+pub use doctor::handle_doctor;
+pub use download::Downloader;
+pub use library::Library;
+pub use links::handle_backlinks;
+pub use rating::handle_rate;
+pub use scan::handle_scan;
+pub use search::PaperFilter;
+pub use sessions::handle_log;
+pub use status::{ReadingStatus, handle_list, handle_queue, handle_status};
+pub use trash::{handle_trash_empty, handle_trash_list, handle_trash_restore, purge_expired_trash};
+pub use typst::handle_notes_build;
+pub use viewer::handle_open;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum TagSelection {
     Tag {
@@ -108,105 +163,340 @@ async fn tag_paper(conn: &libsql::Connection, paper_id: u32, tag_names: Vec<Stri
     Ok(())
 }
 
-pub async fn handle_add(conn: &libsql::Connection) -> Result<()> {
+struct NewPaper {
+    title: String,
+    /// Authors and year from the PDF's metadata, if it has any
+    authors: Option<String>,
+    year: Option<String>,
+    url: String,
+    citation: Option<String>,
+    tag_names: Vec<String>,
+    /// Contents of `summary/main.typ` when a new directory is created
+    notes: String,
+}
+
+#[derive(Debug)]
+enum ConflictResolution {
+    UpdateInPlace,
+    Overwrite,
+    Cancel,
+}
+
+impl fmt::Display for ConflictResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UpdateInPlace => {
+                write!(f, "Update in place (replace the PDF, keep notes and tags)")
+            }
+            Self::Overwrite => write!(f, "Overwrite (delete the old directory, including notes)"),
+            Self::Cancel => write!(f, "Cancel"),
+        }
+    }
+}
+
+/// Where the PDF of a new or refreshed paper comes from.
+enum PdfSource {
+    /// A finished download, removed once this is dropped
+    Downloaded(Download),
+    /// A local file from `papr add --file`, removed afterwards with `--move`
+    Local { path: PathBuf, moved: bool },
+}
+
+impl PdfSource {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Downloaded(download) => download.path(),
+            Self::Local { path, .. } => path,
+        }
+    }
+
+    /// Clean up once the PDF has been copied into the library.
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Local { path, moved: true } => {
+                fs::remove_file(&path).with_context(|| format!("Error removing {:?}.", path))
+            }
+            Self::Downloaded(_) | Self::Local { moved: false, .. } => Ok(()),
+        }
+    }
+}
+
+pub async fn handle_add(
+    conn: &libsql::Connection,
+    library: &Library,
+    downloader: &Downloader,
+    config: &Config,
+    file: Option<PathBuf>,
+    move_file: bool,
+    template: Option<String>,
+) -> Result<()> {
+    // Check the local PDF before asking for anything, then prefill what we
+    // can from its own metadata
+    let metadata = match &file {
+        Some(path) => {
+            if !path.is_file() {
+                anyhow::bail!("{:?} does not exist or is not a file.", path);
+            }
+            if !is_pdf_file(path)? {
+                anyhow::bail!("{:?} is not a PDF file.", path);
+            }
+            read_pdf_metadata(path).unwrap_or_default()
+        }
+        None => PdfMetadata::default(),
+    };
+
     // Prompt for title and URL
     let title = Text::new("Paper title (used for directory name):")
+        .with_initial_value(metadata.title.as_deref().unwrap_or_default())
         .prompt()
         .context("Invalid title.")?;
-    let directory_name = title.to_lowercase().replace(" ", "_");
-    let url = Text::new("Paper PDF URL:")
-        .prompt()
-        .context("Invalid URL.")?;
+    let url = if file.is_some() {
+        Text::new("Paper URL (optional):")
+            .prompt_skippable()
+            .context("Invalid URL.")?
+            .unwrap_or_default()
+    } else {
+        Text::new("Paper PDF URL:")
+            .with_validator(ValueRequiredValidator::new(
+                "A URL is required without --file.",
+            ))
+            .prompt()
+            .context("Invalid URL.")?
+    };
     let citation = Editor::new("Paper citation:")
+        .with_predefined_text(&metadata.citation().unwrap_or_default())
         .with_help_message("Save and exit editor to confirm changes.")
         .prompt_skippable()
         .context("Invalid citation.")?;
     let final_tag_names = get_tag_selections(conn).await?;
+    let template_name = templates::choose_template(&config.templates, template, &final_tag_names);
+    let template = templates::load_template(&template_name)?;
 
     // Start downloading PDF before creating any directories for easy clean-up,
     // in case of failure to retrieve from URL
-    println!("Downloading PDF...");
-    let response = reqwest::get(url.as_str())
-        .await
-        .context("Error downloading PDF.")?;
-    let content = response
-        .bytes()
-        .await
-        .context("Did not receive response when downloading PDF.")?;
+    let source = match file {
+        Some(path) => PdfSource::Local {
+            path,
+            moved: move_file,
+        },
+        None => PdfSource::Downloaded(downloader.download_pdf(&url).await?),
+    };
+
+    let fingerprint = Fingerprint::new(
+        &title,
+        &url,
+        citation.as_deref().unwrap_or_default(),
+        Some(source.path()),
+    )?;
+    let duplicates = dedupe::find_duplicates(conn, library, &fingerprint).await?;
+
+    // Name the directory from the PDF's metadata, falling back to the citation
+    let pdf_metadata = match &source {
+        PdfSource::Local { .. } => metadata,
+        PdfSource::Downloaded(download) => read_pdf_metadata(download.path()).unwrap_or_default(),
+    };
+    let mut name_parts = NameParts {
+        title: title.clone(),
+        authors: pdf_metadata.authors.clone(),
+        year: pdf_metadata.year.clone(),
+    };
+    name_parts.fill_from_citation(citation.as_deref().unwrap_or_default());
+    let notes = templates::render(
+        &template,
+        &templates::NoteValues {
+            parts: &name_parts,
+            url: &url,
+            tag_names: &final_tag_names,
+        },
+    );
+    let directory_name = naming::directory_name(&name_parts, &config.naming)?;
+    let cwd =
+        fs::canonicalize(Path::new(".")).context("Error canonicalizing current directory.")?;
 
     // Setup directory structure for this new paper
-    // Prompt user to overwrite if the canonicalized path already exists
+    // If the same paper is already at this path, prompt to update or overwrite it.
+    // A path taken by a different paper or an untracked directory gets a suffix.
     // Note that the entire path, not just the paper name has to match
-    let base_path = Path::new(".").join(&directory_name);
-    let summary_path = base_path.join("summary");
-    let canonical_base_path = fs::canonicalize(Path::new("."))
-        .context("Error canonicalizing current directory.")?
-        .join(&directory_name)
-        .into_os_string()
-        .into_string()
-        .unwrap();
+    let mut existing_id = None;
+    let mut attempt = 1;
+    let (base_path, canonical_base_path) = loop {
+        let name = naming::with_suffix(&directory_name, attempt);
+        let base_path = Path::new(".").join(&name);
+        let canonical_base_path = library.to_stored(&cwd.join(&name))?;
+
+        let mut rows = conn
+            .query(
+                "SELECT id, deleted_at FROM papers WHERE canonical_base_path = ?1",
+                [canonical_base_path.clone()],
+            )
+            .await?;
+        let taken_by = match rows.next().await? {
+            Some(row) => Some((row.get::<u32>(0)?, row.get::<Option<String>>(1)?)),
+            None => None,
+        };
+
+        match taken_by {
+            Some((id, None)) if duplicates.iter().any(|d| d.id == id && d.certain) => {
+                let resolution = Select::new(
+                    &format!(
+                        "Paper '{}' already exists in the database at {}. What should be done?",
+                        title,
+                        library.resolve_string(&canonical_base_path)
+                    ),
+                    vec![
+                        ConflictResolution::UpdateInPlace,
+                        ConflictResolution::Overwrite,
+                        ConflictResolution::Cancel,
+                    ],
+                )
+                .prompt()?;
 
-    let mut rows = conn
-        .query(
-            "SELECT canonical_base_path FROM papers WHERE canonical_base_path = ?1",
-            [canonical_base_path.clone()],
-        )
-        .await?;
+                match resolution {
+                    ConflictResolution::UpdateInPlace => {
+                        let paper = NewPaper {
+                            title,
+                            authors: pdf_metadata.authors,
+                            year: pdf_metadata.year,
+                            url,
+                            citation,
+                            tag_names: final_tag_names,
+                            notes,
+                        };
+                        update_paper_in_place(conn, id, &base_path, &paper, source.path()).await?;
+                        source.finish()?;
+                        println!("Successfully updated '{}' in place!", paper.title);
+                        return Ok(());
+                    }
+                    ConflictResolution::Overwrite => {
+                        existing_id = Some(id);
+                        break (base_path, canonical_base_path);
+                    }
+                    ConflictResolution::Cancel => {
+                        println!("Add operation cancelled.");
+                        return Ok(());
+                    }
+                }
+            }
+            None if !base_path.exists() => break (base_path, canonical_base_path),
+            _ => attempt += 1,
+        }
+    };
+    if attempt > 1 {
+        println!(
+            "'{}' is taken, using '{}' instead.",
+            directory_name,
+            base_path.display()
+        );
+    }
 
-    if let Some(row) = rows.next().await? {
-        let existing_canonicalized_path: String = row.get(0)?;
+    // An overwritten paper is being replaced on purpose, so only look for
+    // duplicates under other names
+    if existing_id.is_none() {
+        match dedupe::prompt_duplicate_action(duplicates)? {
+            (DuplicateAction::Merge, Some(target_id)) => {
+                dedupe::merge_into(
+                    conn,
+                    target_id,
+                    &url,
+                    citation.as_deref().unwrap_or_default(),
+                    final_tag_names,
+                )
+                .await?;
+                source.finish()?;
+                println!("Merged '{}' into the existing paper.", title);
+                return Ok(());
+            }
+            (DuplicateAction::Skip, _) => {
+                println!("Add operation cancelled.");
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
 
-        let ans = Confirm::new(&format!(
-            "Paper '{}' already exists in the database at {}. Overwrite?",
-            title, existing_canonicalized_path
-        ))
-        .with_default(false)
-        .with_help_message("This will update the DB entry and remove the old paper directory. This means that your notes will be deleted.")
-        .prompt()?;
+    let paper = NewPaper {
+        title,
+        authors: pdf_metadata.authors,
+        year: pdf_metadata.year,
+        url,
+        citation,
+        tag_names: final_tag_names,
+        notes,
+    };
 
-        if ans {
-            fs::remove_dir_all(&base_path)?;
-        } else {
-            println!("Add operation cancelled.");
-            return Ok(());
+    // Dropping the commit future on Ctrl+C rolls back both the DB transaction
+    // and the staged directory
+    let commit = commit_new_paper(
+        conn,
+        &paper,
+        &base_path,
+        canonical_base_path,
+        existing_id,
+        source.path(),
+        &fingerprint,
+    );
+    tokio::select! {
+        res = commit => res?,
+        _ = tokio::signal::ctrl_c() => {
+            anyhow::bail!("Add operation interrupted, no changes were made.")
         }
     }
+    source.finish()?;
 
-    fs::create_dir_all(&base_path).context("Error creating base directory.")?;
+    println!("Successfully added '{}' to your library!", paper.title);
+    Ok(())
+}
+
+/// Write the paper directory to a staging location and record it in the DB,
+/// only swapping the directory into place once the DB transaction can commit.
+/// Any failure leaves both the filesystem and the DB as they were.
+async fn commit_new_paper(
+    conn: &libsql::Connection,
+    paper: &NewPaper,
+    base_path: &Path,
+    canonical_base_path: String,
+    existing_id: Option<u32>,
+    pdf_source: &Path,
+    fingerprint: &Fingerprint,
+) -> Result<()> {
+    let mut staged = StagedDir::new(base_path)?;
+    let summary_path = staged.path().join("summary");
     fs::create_dir_all(&summary_path).context("Error creating summary directory")?;
 
-    // Download PDF
-    let pdf_file_path = base_path.join("paper.pdf");
-    let mut file = fs::File::create(&pdf_file_path)?;
-    std::io::copy(&mut content.as_ref(), &mut file)?;
+    fs::copy(pdf_source, staged.path().join("paper.pdf")).context("Error copying PDF.")?;
 
     // Create `main.typ` entry point
-    let typ_content = format!(
-        "#set text(font: \"New Computer Modern\")
-#show heading: it => [#it #v(0.2em)]\n
-#text(size: 2em)[#link(\"{}\")[{}]]\n",
-        url, title
-    );
-    fs::write(summary_path.join("main.typ"), typ_content)?;
+    fs::write(summary_path.join("main.typ"), &paper.notes)?;
+
+    let tx = conn.transaction().await?;
+
+    // The replaced row gets a new id, so drop the old links with it
+    if let Some(old_id) = existing_id {
+        db::delete_paper_links(&tx, old_id).await?;
+    }
 
     // Update papers table
-    conn.execute(
-        "INSERT OR REPLACE INTO papers (canonical_base_path, url, date_added, citation) VALUES (?1, ?2, ?3, ?4)",
+    tx.execute(
+        "INSERT OR REPLACE INTO papers (canonical_base_path, url, date_added, citation, title, authors, year)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
         (
             canonical_base_path.clone(),
-            url.clone(),
+            paper.url.clone(),
             Local::now().format("%Y-%m-%d").to_string(),
-            citation.unwrap_or_default(),
+            paper.citation.clone().unwrap_or_default(),
+            paper.title.clone(),
+            paper.authors.clone(),
+            paper.year.clone(),
         ),
     )
     .await
     .context("Error updating papers table.")?;
 
     // Update the tags and paper_tags tables
-    let paper_id: u32 = conn
+    let paper_id: u32 = tx
         .query(
             "select id from papers where canonical_base_path = ?1",
-            [canonical_base_path.clone()],
+            [canonical_base_path],
         )
         .await?
         .next()
@@ -214,14 +504,180 @@ pub async fn handle_add(conn: &libsql::Connection) -> Result<()> {
         .unwrap()
         .get(0)?;
 
-    tag_paper(conn, paper_id, final_tag_names).await?;
+    tag_paper(&tx, paper_id, paper.tag_names.clone()).await?;
+    dedupe::store_fingerprint(&tx, paper_id, fingerprint).await?;
+
+    if existing_id.is_some() {
+        tx.execute(
+            "DELETE FROM tags WHERE id NOT IN (SELECT DISTINCT tag_id FROM paper_tags)",
+            (),
+        )
+        .await?;
+    }
+
+    staged.swap_in()?;
+    tx.commit()
+        .await
+        .context("Error committing paper to the database.")?;
+    staged.finish()?;
 
-    println!("Successfully added '{}' to your library!", title);
     Ok(())
 }
 
-pub async fn handle_remove(conn: &libsql::Connection, query: String) -> Result<()> {
-    let matching_papers = search::fuzzy_search_papers(conn, &query).await?;
+/// Replace only `paper.pdf` of an existing paper, keeping the previous PDF as
+/// a timestamped backup. The notes, the paper id and its tag links are kept;
+/// the URL and citation are updated only if new ones were given, and any
+/// selected tags are added to the existing ones.
+async fn update_paper_in_place(
+    conn: &libsql::Connection,
+    id: u32,
+    base_path: &Path,
+    paper: &NewPaper,
+    pdf_source: &Path,
+) -> Result<()> {
+    let pdf_path = base_path.join("paper.pdf");
+    let new_pdf_path = base_path.join("paper.pdf.papr-new");
+    fs::create_dir_all(base_path).context("Error creating base directory.")?;
+    fs::copy(pdf_source, &new_pdf_path).context("Error copying PDF.")?;
+
+    let res = async {
+        let tx = conn.transaction().await?;
+        if !paper.url.is_empty() {
+            tx.execute(
+                "UPDATE papers SET url = ?1 WHERE id = ?2",
+                (paper.url.clone(), id),
+            )
+            .await
+            .context("Error updating papers table.")?;
+        }
+        if let Some(citation) = &paper.citation {
+            tx.execute(
+                "UPDATE papers SET citation = ?1 WHERE id = ?2",
+                (citation.clone(), id),
+            )
+            .await
+            .context("Error updating papers table.")?;
+        }
+        tag_paper(&tx, id, paper.tag_names.clone()).await?;
+        let fingerprint = Fingerprint::new(
+            &paper.title,
+            &paper.url,
+            paper.citation.as_deref().unwrap_or_default(),
+            Some(pdf_source),
+        )?;
+        dedupe::store_fingerprint(&tx, id, &fingerprint).await?;
+
+        let backup_path = if pdf_path.exists() {
+            let backup_path = base_path.join(format!(
+                "paper.pdf.{}.bak",
+                Local::now().format("%Y%m%d%H%M%S")
+            ));
+            fs::rename(&pdf_path, &backup_path).context("Error backing up old PDF.")?;
+            Some(backup_path)
+        } else {
+            None
+        };
+
+        let swapped = fs::rename(&new_pdf_path, &pdf_path).context("Error replacing PDF.");
+        let committed = match swapped {
+            Ok(()) => tx
+                .commit()
+                .await
+                .context("Error committing paper to the database."),
+            Err(e) => Err(e),
+        };
+
+        match (committed, backup_path) {
+            (Ok(()), Some(backup_path)) => {
+                println!("Previous PDF kept at {}", backup_path.display());
+                Ok(())
+            }
+            (Ok(()), None) => Ok(()),
+            (Err(e), backup_path) => {
+                let _ = fs::remove_file(&pdf_path);
+                if let Some(backup_path) = backup_path {
+                    let _ = fs::rename(&backup_path, &pdf_path);
+                }
+                Err(e)
+            }
+        }
+    }
+    .await;
+
+    if new_pdf_path.exists() {
+        let _ = fs::remove_file(&new_pdf_path);
+    }
+    res
+}
+
+pub async fn handle_refetch(
+    conn: &libsql::Connection,
+    library: &Library,
+    downloader: &Downloader,
+    query: String,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper to refetch:", matching_papers)
+        .prompt()
+        .context("No paper selected for refetching.")?;
+
+    let mut rows = conn
+        .query(
+            "SELECT url, citation FROM papers WHERE id = ?1",
+            [paper_selection.id],
+        )
+        .await?;
+    let (current_url, current_citation): (String, String) = match rows.next().await? {
+        Some(row) => (row.get(0)?, row.get(1)?),
+        None => anyhow::bail!("Paper ID {} not found in database.", paper_selection.id),
+    };
+
+    let url = Text::new("Paper PDF URL:")
+        .with_initial_value(&current_url)
+        .prompt()
+        .context("Invalid URL.")?;
+    let citation = Editor::new("Paper citation:")
+        .with_predefined_text(&current_citation)
+        .with_help_message(
+            "Save and exit editor to confirm changes. Skip to keep the current citation.",
+        )
+        .prompt_skippable()
+        .context("Invalid citation.")?;
+
+    let source = PdfSource::Downloaded(downloader.download_pdf(&url).await?);
+
+    let base_path = Path::new(&paper_selection.canonical_base_path);
+    let paper = NewPaper {
+        title: base_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Unknown")
+            .to_string(),
+        authors: None,
+        year: None,
+        url,
+        citation,
+        tag_names: Vec::new(),
+        // Refetching keeps the existing notes
+        notes: String::new(),
+    };
+    update_paper_in_place(conn, paper_selection.id, base_path, &paper, source.path()).await?;
+    source.finish()?;
+
+    println!("Successfully refetched '{}'!", paper.title);
+    Ok(())
+}
+
+pub async fn handle_remove(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+    trash_dir: &Path,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
     if matching_papers.is_empty() {
         anyhow::bail!("No papers found matching '{}'", query);
     }
@@ -238,46 +694,134 @@ pub async fn handle_remove(conn: &libsql::Connection, query: String) -> Result<(
         ..
     } in paper_selections
     {
-        // Delete the paper (this triggers cascade to clear paper_tags)
-        conn.execute("DELETE FROM papers WHERE id = ?1", [id])
-            .await?;
+        // Keep the row and tag links around so the paper can be restored from the trash
+        trash::trash_paper(conn, library, trash_dir, id, &canonical_base_path).await?;
+    }
 
-        // Prune orphan tags that no longer belong to any paper
-        conn.execute(
-            "DELETE FROM tags 
-             WHERE id NOT IN (SELECT DISTINCT tag_id FROM paper_tags)",
-            (),
+    Ok(())
+}
+
+pub async fn handle_move(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+    destination: PathBuf,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper to move:", matching_papers)
+        .prompt()
+        .context("No paper selected for moving.")?;
+
+    let source = PathBuf::from(&paper_selection.canonical_base_path);
+
+    // Moving into an existing directory keeps the paper's directory name,
+    // otherwise the destination is the new path of the paper directory
+    let destination = if destination.is_dir() {
+        let dir_name = source
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Invalid paper path {:?}.", source))?;
+        destination.join(dir_name)
+    } else {
+        destination
+    };
+    let dir_name = destination
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid destination {:?}.", destination))?;
+    let parent = match destination.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let canonical_destination = fs::canonicalize(parent)
+        .with_context(|| format!("Destination directory {:?} does not exist.", parent))?
+        .join(dir_name);
+
+    if canonical_destination.exists() {
+        anyhow::bail!(
+            "Refusing to overwrite existing path {:?}.",
+            canonical_destination
+        );
+    }
+    if let Ok(canonical_source) = fs::canonicalize(&source)
+        && canonical_destination.starts_with(&canonical_source)
+    {
+        anyhow::bail!("Cannot move {:?} into itself.", source);
+    }
+    let canonical_destination_str = library.to_stored(&canonical_destination)?;
+
+    let mut rows = conn
+        .query(
+            "SELECT id FROM papers WHERE canonical_base_path = ?1",
+            [canonical_destination_str.clone()],
         )
         .await?;
+    if rows.next().await?.is_some() {
+        anyhow::bail!(
+            "Another paper is already registered at {:?}.",
+            canonical_destination
+        );
+    }
+
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "UPDATE papers SET canonical_base_path = ?1 WHERE id = ?2",
+        (canonical_destination_str, paper_selection.id),
+    )
+    .await
+    .context("Error updating papers table.")?;
 
-        fs::remove_dir_all(&canonical_base_path)?;
+    fsutil::move_dir(&source, &canonical_destination)?;
+
+    if let Err(e) = tx.commit().await {
+        // Put the directory back where the DB still says it is
+        fsutil::move_dir(&canonical_destination, &source)?;
+        return Err(e).context("Error committing move to the database.");
     }
 
+    println!(
+        "Moved '{}' to '{}'.",
+        source.display(),
+        canonical_destination.display()
+    );
     Ok(())
 }
 
 pub async fn handle_search(
     conn: &libsql::Connection,
+    library: &Library,
+    viewer_config: &ViewerConfig,
     query: String,
-    tags: Option<Vec<String>>,
+    filter: PaperFilter,
     pdf: bool,
+    interactive: bool,
 ) -> Result<()> {
     if pdf {
-        let results = search::fuzzy_search_pdfs(conn, &query, tags).await?;
+        let results = search::fuzzy_search_pdfs(conn, library, &query, &filter).await?;
+        if interactive {
+            let hits = results.into_iter().map(Hit::Pdf).collect();
+            return browse::browse_hits(conn, viewer_config, hits).await;
+        }
         for pdf_match_result in results {
             println!(
-                "Paper name: {} ({})\nPage: {}\nExcerpt: {}\n",
+                "Paper name: {} ({})\nSource: {}\nPage: {}\nExcerpt: {}\n",
                 Path::new(&pdf_match_result.canonical_path)
                     .file_name()
                     .and_then(|s| s.to_str())
                     .unwrap_or("Unknown"),
                 pdf_match_result.canonical_path,
+                pdf_match_result.source,
                 pdf_match_result.page,
                 pdf_match_result.excerpt
             );
         }
     } else {
-        let results = search::fuzzy_search_typst(conn, &query, tags).await?;
+        let results = search::fuzzy_search_typst(conn, library, &query, &filter).await?;
+        if interactive {
+            let hits = results.into_iter().map(Hit::Note).collect();
+            return browse::browse_hits(conn, viewer_config, hits).await;
+        }
         for typst_match_result in results {
             println!(
                 "Paper name: {} ({})\nLine: {}\nExcerpt: {}\n",
@@ -295,8 +839,12 @@ pub async fn handle_search(
     Ok(())
 }
 
-pub async fn handle_retag(conn: &libsql::Connection, query: String) -> Result<()> {
-    let matching_papers = search::fuzzy_search_papers(conn, &query).await?;
+pub async fn handle_retag(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
     if matching_papers.is_empty() {
         anyhow::bail!("No papers found matching '{}'", query);
     }
@@ -304,8 +852,11 @@ pub async fn handle_retag(conn: &libsql::Connection, query: String) -> Result<()
         .prompt()
         .context("No paper selected for retagging.")?;
 
-    let paper_id = paper_selection.id;
+    retag_paper(conn, paper_selection.id).await
+}
 
+/// Replace the tags of a paper with ones picked by the user.
+async fn retag_paper(conn: &libsql::Connection, paper_id: u32) -> Result<()> {
     let final_tag_names = get_tag_selections(conn).await?;
 
     // Clear existing associations for this paper
@@ -325,8 +876,12 @@ pub async fn handle_retag(conn: &libsql::Connection, query: String) -> Result<()
     Ok(())
 }
 
-pub async fn handle_cite(conn: &libsql::Connection, query: String) -> Result<()> {
-    let matching_papers = search::fuzzy_search_papers(conn, &query).await?;
+pub async fn handle_cite(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
     if matching_papers.is_empty() {
         anyhow::bail!("No papers found matching '{}'", query);
     }
@@ -334,8 +889,10 @@ pub async fn handle_cite(conn: &libsql::Connection, query: String) -> Result<()>
         .prompt()
         .context("No paper selected for retagging.")?;
 
-    let paper_id = paper_selection.id;
+    edit_citation(conn, paper_selection.id).await
+}
 
+async fn edit_citation(conn: &libsql::Connection, paper_id: u32) -> Result<()> {
     // Fetch the current citation value
     let mut rows = conn
         .query("SELECT citation FROM papers WHERE id = ?1", [paper_id])
@@ -370,8 +927,13 @@ pub async fn handle_cite(conn: &libsql::Connection, query: String) -> Result<()>
     Ok(())
 }
 
-pub async fn handle_notes(conn: &libsql::Connection, query: String) -> Result<()> {
-    let matching_papers = search::fuzzy_search_papers(conn, &query).await?;
+pub async fn handle_notes(
+    conn: &libsql::Connection,
+    library: &Library,
+    viewer_config: &ViewerConfig,
+    query: String,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
     if matching_papers.is_empty() {
         anyhow::bail!("No papers found matching '{}'", query);
     }
@@ -382,56 +944,76 @@ pub async fn handle_notes(conn: &libsql::Connection, query: String) -> Result<()
     let base_path_str = paper_selection.canonical_base_path;
     let summary_dir = Path::new(&base_path_str).join("summary");
 
-    // Locate the source .typ file
-    let mut typst_file = summary_dir.join("main.typ");
-    if !typst_file.exists() {
-        let first_typ = std::fs::read_dir(&summary_dir)?
-            .filter_map(|e| e.ok())
-            .find(|e| e.path().extension().is_some_and(|ext| ext == "typ"))
-            .map(|e| e.path());
-
-        match first_typ {
-            Some(path) => typst_file = path,
-            None => anyhow::bail!("No .typ files found in {:?}", summary_dir),
-        }
-    }
-
+    let typst_file = find_typst_file(&summary_dir)?;
     let output_pdf = typst_file.with_extension("pdf");
+    // Read once when starting, so DB changes such as a new citation or tags
+    // take effect on the next `papr notes`
+    let inputs = typst::paper_inputs(conn, library, paper_selection.id).await?;
+
+    let wrapper = links::write_wrapper(&typst_file)?;
 
     // Force an initial compile so the file always exists
     println!("Performing initial build...");
     Command::new("typst")
-        .arg("compile")
-        .arg(&typst_file)
-        .arg(&output_pdf)
+        .args(typst::typst_args("compile", &wrapper, &output_pdf, &inputs))
         .status()?;
 
     // Open the PDF viewer first
     if output_pdf.exists() {
         println!("Opening PDF viewer...");
-        let _ = open::that(&output_pdf);
+        if let Err(e) = viewer::open_pdf(viewer_config, &output_pdf, None) {
+            eprintln!("{:#}", e);
+        }
     }
 
     // Run 'typst watch', blocking the current terminal session
     println!("Watch mode started for {}...", typst_file.display());
     println!("Press Ctrl+C to stop watching.");
 
-    let mut child = Command::new("typst")
-        .arg("watch")
-        .arg(&typst_file)
-        .arg(&output_pdf)
+    let mut child = tokio::process::Command::new("typst")
+        .args(typst::typst_args("watch", &wrapper, &output_pdf, &inputs))
         .spawn() // Use spawn instead of status so we can manage the process if needed
         .context("Failed to start 'typst watch'. Is it installed?")?;
 
-    let status = child.wait()?;
+    // The session lasts as long as the watcher. Ctrl+C also reaches typst, so
+    // catching it here only keeps papr alive long enough to record the end.
+    let session_id = sessions::start_session(conn, paper_selection.id).await?;
+    let status = tokio::select! {
+        status = child.wait() => Some(status?),
+        _ = tokio::signal::ctrl_c() => {
+            let _ = child.wait().await;
+            None
+        }
+    };
+    let duration = sessions::end_session(conn, session_id).await?;
+    links::index_paper(conn, paper_selection.id, Path::new(&base_path_str)).await?;
+    println!("\nSession length: {}", sessions::format_duration(duration));
 
-    if !status.success() {
+    if status.is_some_and(|status| !status.success()) {
         anyhow::bail!("Typst watch exited with an error.");
     }
 
     Ok(())
 }
 
+/// The `.typ` file holding a paper's notes: `main.typ`, or else the first
+/// one found.
+fn find_typst_file(summary_dir: &Path) -> Result<PathBuf> {
+    let typst_file = summary_dir.join("main.typ");
+    if typst_file.exists() {
+        return Ok(typst_file);
+    }
+    let first_typ = std::fs::read_dir(summary_dir)?
+        .filter_map(|e| e.ok())
+        .find(|e| links::is_note_file(&e.path()))
+        .map(|e| e.path());
+
+    match first_typ {
+        Some(path) => Ok(path),
+        None => anyhow::bail!("No .typ files found in {:?}", summary_dir),
+    }
+}
+
 pub fn get_db_path(global: bool) -> Result<PathBuf> {
     if global {
         let proj_dirs = ProjectDirs::from("com", "", "papr")
@@ -447,12 +1029,23 @@ pub fn get_db_path(global: bool) -> Result<PathBuf> {
     }
 }
 
+pub fn get_trash_dir(global: bool) -> Result<PathBuf> {
+    if global {
+        let proj_dirs = ProjectDirs::from("com", "", "papr")
+            .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+        Ok(proj_dirs.data_dir().join("trash"))
+    } else {
+        Ok(Path::new(".").join(".papr_trash"))
+    }
+}
+
 async fn get_all_tags(conn: &libsql::Connection) -> Result<Vec<TagSelection>> {
     let mut rows = conn
         .query(
-            "SELECT t.name, COUNT(pt.paper_id), t.id as count
+            "SELECT t.name, COUNT(p.id), t.id as count
                 FROM tags t
                 LEFT JOIN paper_tags pt ON t.id = pt.tag_id
+                LEFT JOIN papers p ON p.id = pt.paper_id AND p.deleted_at IS NULL
                 GROUP BY t.name
                 ORDER BY count DESC;",
             (),
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use directories::BaseDirs;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::db::{get_meta, set_meta};
+
+/// The directory that paper paths in the database are relative to.
+///
+/// A local database lives in its library root, so the root always follows
+/// the database file around. The global database uses the root from the
+/// config, which is recorded in the `meta` table so it is still used if the
+/// config is later lost. Without either, the root is the home directory.
+/// Paths outside the root are stored as absolute paths.
+#[derive(Debug, Clone)]
+pub struct Library {
+    root: PathBuf,
+}
+
+impl Library {
+    pub async fn open(
+        conn: &libsql::Connection,
+        db_path: &Path,
+        global: bool,
+        config: &Config,
+    ) -> Result<Self> {
+        let configured = config.library.global_root.as_ref().filter(|_| global);
+        let root = if !global {
+            match db_path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+                _ => PathBuf::from("."),
+            }
+        } else if let Some(root) = configured {
+            root.clone()
+        } else if let Some(root) = get_meta(conn, "library_root").await? {
+            PathBuf::from(root)
+        } else {
+            // Not recorded, so the library follows the home directory
+            BaseDirs::new()
+                .ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?
+                .home_dir()
+                .to_path_buf()
+        };
+
+        let root = fs::canonicalize(&root)
+            .with_context(|| format!("Library root {:?} does not exist.", root))?;
+        if configured.is_some() {
+            set_meta(conn, "library_root", &root.to_string_lossy()).await?;
+        }
+        let library = Self { root };
+        library.migrate_absolute_paths(conn).await?;
+
+        Ok(library)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Turn a path as stored in the database into an absolute path.
+    pub fn resolve(&self, stored: &str) -> PathBuf {
+        let path = Path::new(stored);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.root.join(path)
+        }
+    }
+
+    /// Like `resolve`, for callers that pass paths around as strings.
+    pub fn resolve_string(&self, stored: &str) -> String {
+        self.resolve(stored).to_string_lossy().into_owned()
+    }
+
+    /// Turn an absolute path into the form stored in the database.
+    pub fn to_stored(&self, path: &Path) -> Result<String> {
+        let stored = path.strip_prefix(&self.root).unwrap_or(path);
+        stored
+            .to_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("Path {:?} is not valid UTF-8.", path))
+    }
+
+    /// One-off conversion of the absolute paths written by older versions.
+    async fn migrate_absolute_paths(&self, conn: &libsql::Connection) -> Result<()> {
+        if get_meta(conn, "relative_paths").await?.is_some() {
+            return Ok(());
+        }
+
+        let mut rows = conn
+            .query("SELECT id, canonical_base_path, trash_path FROM papers", ())
+            .await?;
+        let mut updates = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let id: u32 = row.get(0)?;
+            let base_path: String = row.get(1)?;
+            let trash_path: Option<String> = row.get(2)?;
+            updates.push((
+                id,
+                self.to_stored(Path::new(&base_path))?,
+                trash_path
+                    .map(|p| self.to_stored(Path::new(&p)))
+                    .transpose()?,
+            ));
+        }
+
+        let tx = conn.transaction().await?;
+        for (id, base_path, trash_path) in updates {
+            tx.execute(
+                "UPDATE papers SET canonical_base_path = ?1, trash_path = ?2 WHERE id = ?3",
+                (base_path, trash_path, id),
+            )
+            .await
+            .context("Error converting paper paths to relative paths.")?;
+        }
+        set_meta(&tx, "relative_paths", "1").await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::memory_db;
+
+    #[test]
+    fn stores_paths_under_the_root_relatively() {
+        let library = Library {
+            root: PathBuf::from("/papers"),
+        };
+        assert_eq!(library.to_stored(Path::new("/papers/a/b")).unwrap(), "a/b");
+        assert_eq!(
+            library.to_stored(Path::new("/other/c")).unwrap(),
+            "/other/c"
+        );
+        assert_eq!(library.resolve("a/b"), Path::new("/papers/a/b"));
+        assert_eq!(library.resolve("/other/c"), Path::new("/other/c"));
+    }
+
+    #[tokio::test]
+    async fn records_only_a_configured_global_root() {
+        let conn = memory_db().await;
+        let db_path = Path::new("papr.db");
+        let mut config = Config::default();
+
+        Library::open(&conn, db_path, true, &config).await.unwrap();
+        assert_eq!(get_meta(&conn, "library_root").await.unwrap(), None);
+
+        let root = tempfile::tempdir().unwrap();
+        config.library.global_root = Some(root.path().to_path_buf());
+        Library::open(&conn, db_path, true, &config).await.unwrap();
+
+        // Still used once the config no longer sets it
+        config.library.global_root = None;
+        let library = Library::open(&conn, db_path, true, &config).await.unwrap();
+        assert_eq!(library.root(), fs::canonicalize(root.path()).unwrap());
+    }
+}
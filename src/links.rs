@@ -0,0 +1,224 @@
+use anyhow::{Context, Result};
+use inquire::Select;
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::library::Library;
+use crate::search;
+use crate::templates::escape;
+
+/// A reference to another paper in notes, `@papr:<id>`. This is Typst's
+/// reference syntax, so the link becomes a real cross-reference in a digest.
+static LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"@papr:(\d+)\b").unwrap());
+
+/// Typst has no label to point `@papr:<id>` at unless the paper is part of
+/// the document, as in a digest, so anywhere else the paper id is shown.
+pub const LINK_RULE: &str = r#"#show ref: it => context {
+  let target = str(it.target)
+  if target.starts-with("papr:") and query(it.target).len() == 0 {
+    emph[paper #target.slice(5)]
+  } else {
+    it
+  }
+}
+"#;
+
+/// Whether `path` is a notes file, rather than the wrapper papr compiles
+/// notes through.
+pub fn is_note_file(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "typ")
+        && !path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(|name| name.starts_with('.'))
+}
+
+/// Write the file that papr compiles `typst_file` through, next to it, which
+/// applies `LINK_RULE` so links compile whatever template the notes use. It
+/// is only rewritten when it changes, to leave finished builds up to date.
+pub fn write_wrapper(typst_file: &Path) -> Result<PathBuf> {
+    let file_name = typst_file
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid notes file {:?}.", typst_file))?;
+    let wrapper = typst_file.with_file_name(format!(".papr-{}", file_name));
+    let content = format!("{}#include \"{}\"\n", LINK_RULE, escape(file_name));
+    if fs::read_to_string(&wrapper).ok().as_deref() != Some(content.as_str()) {
+        fs::write(&wrapper, content)
+            .with_context(|| format!("Error writing {}.", wrapper.display()))?;
+    }
+    Ok(wrapper)
+}
+
+/// A mention of another paper in the notes of a paper.
+pub struct NoteLink {
+    pub target_id: u32,
+    /// Name of the `.typ` file in the `summary` directory
+    pub file: String,
+    pub line: usize,
+}
+
+pub fn find_links(file: &str, content: &str) -> Vec<NoteLink> {
+    let mut links = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let mut targets = Vec::new();
+        for caps in LINK.captures_iter(line) {
+            // A paper mentioned twice on one line is listed once
+            if let Ok(target_id) = caps[1].parse::<u32>()
+                && !targets.contains(&target_id)
+            {
+                targets.push(target_id);
+                links.push(NoteLink {
+                    target_id,
+                    file: file.to_string(),
+                    line: i + 1,
+                });
+            }
+        }
+    }
+    links
+}
+
+/// Replace the indexed links of a paper's notes with `links`.
+pub async fn store_links(
+    conn: &libsql::Connection,
+    paper_id: u32,
+    links: &[NoteLink],
+) -> Result<()> {
+    let tx = conn.transaction().await?;
+    tx.execute("DELETE FROM note_links WHERE paper_id = ?1", [paper_id])
+        .await?;
+    for link in links {
+        tx.execute(
+            "INSERT INTO note_links (paper_id, target_id, file, line) VALUES (?1, ?2, ?3, ?4)",
+            (
+                paper_id,
+                link.target_id,
+                link.file.clone(),
+                link.line as i64,
+            ),
+        )
+        .await?;
+    }
+    tx.commit().await.context("Error updating note links.")?;
+    Ok(())
+}
+
+/// Index the links in every `.typ` file of a paper's notes.
+pub async fn index_paper(conn: &libsql::Connection, paper_id: u32, base_path: &Path) -> Result<()> {
+    let summary_dir = base_path.join("summary");
+    let mut links = Vec::new();
+    if summary_dir.exists() {
+        for entry in fs::read_dir(&summary_dir)? {
+            let path = entry?.path();
+            if is_note_file(&path) {
+                let content = fs::read_to_string(&path)?;
+                let file = path
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default();
+                links.extend(find_links(file, &content));
+            }
+        }
+    }
+    store_links(conn, paper_id, &links).await
+}
+
+pub async fn handle_backlinks(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper:", matching_papers)
+        .prompt()
+        .context("No paper selected.")?;
+
+    let mut rows = conn
+        .query(
+            "SELECT p.canonical_base_path, nl.file, nl.line
+             FROM note_links nl JOIN papers p ON p.id = nl.paper_id
+             WHERE nl.target_id = ?1 AND p.deleted_at IS NULL
+             ORDER BY p.canonical_base_path, nl.file, nl.line",
+            [paper_selection.id],
+        )
+        .await?;
+    let mut count = 0;
+    while let Some(row) = rows.next().await? {
+        count += 1;
+        let file = library
+            .resolve(&row.get::<String>(0)?)
+            .join("summary")
+            .join(row.get::<String>(1)?);
+        let line: usize = row.get::<i64>(2)? as usize;
+        let text = fs::read_to_string(&file)
+            .ok()
+            .and_then(|content| {
+                content
+                    .lines()
+                    .nth(line.saturating_sub(1))
+                    .map(str::to_string)
+            })
+            .unwrap_or_default();
+        println!("{}:{}: {}", file.display(), line, text.trim());
+    }
+
+    if count == 0 {
+        println!(
+            "No notes mention {}. Link to it from notes with @papr:{}.",
+            paper_selection.canonical_base_path, paper_selection.id
+        );
+        println!("Links are indexed whenever notes are searched, built or edited with papr.");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(content: &str) -> Vec<(u32, usize)> {
+        find_links("main.typ", content)
+            .into_iter()
+            .map(|link| (link.target_id, link.line))
+            .collect()
+    }
+
+    #[test]
+    fn lists_each_paper_once_per_line() {
+        assert_eq!(
+            targets("See @papr:12 and @papr:3, unlike @papr:12.\n\nAlso @papr:12"),
+            vec![(12, 1), (3, 1), (12, 3)]
+        );
+    }
+
+    #[test]
+    fn finds_links_at_the_end_of_a_sentence() {
+        assert_eq!(targets("This builds on @papr:12."), vec![(12, 1)]);
+    }
+
+    #[test]
+    fn ignores_links_without_a_numeric_id() {
+        assert!(targets("@papr:abc @papr:12abc @papr: papr:4").is_empty());
+    }
+
+    #[test]
+    fn compiles_notes_through_a_hidden_wrapper() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("main.typ");
+        let wrapper = write_wrapper(&notes).unwrap();
+
+        assert_eq!(wrapper, dir.path().join(".papr-main.typ"));
+        let content = fs::read_to_string(&wrapper).unwrap();
+        assert!(content.starts_with(LINK_RULE));
+        assert!(content.ends_with("#include \"main.typ\"\n"));
+        assert!(is_note_file(&notes));
+        assert!(!is_note_file(&wrapper));
+        assert!(!is_note_file(&dir.path().join("main.pdf")));
+    }
+}
@@ -1,9 +1,18 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
+use chrono::NaiveDate;
 use clap::{ArgAction, Parser, Subcommand};
 use libsql::Builder;
 use papr::{
-    get_db_path, handle_add, handle_cite, handle_notes, handle_remove, handle_retag, handle_search,
+    AttachmentKind, Config, Downloader, Library, PaperFilter, ReadingStatus, get_db_path,
+    get_trash_dir, handle_add, handle_attach, handle_backlinks, handle_cite, handle_collection_add,
+    handle_collection_create, handle_collection_delete, handle_collection_list,
+    handle_collection_remove, handle_collection_reorder, handle_collection_show, handle_dedupe,
+    handle_digest, handle_doctor, handle_list, handle_log, handle_move, handle_notes,
+    handle_notes_build, handle_open, handle_outdated, handle_queue, handle_rate, handle_refetch,
+    handle_remove, handle_retag, handle_scan, handle_search, handle_status, handle_trash_empty,
+    handle_trash_list, handle_trash_restore, init_schema, purge_expired_trash,
 };
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "papr", about = "PhD paper management system.", version)]
@@ -18,8 +27,20 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Add a new paper from a URL
-    Add,
+    /// Add a new paper from a URL or a local PDF file
+    Add {
+        /// Add a local PDF instead of downloading one
+        #[arg(long)]
+        file: Option<PathBuf>,
+
+        /// Move the local PDF into the library instead of copying it
+        #[arg(long = "move", requires = "file")]
+        move_file: bool,
+
+        /// Notes template to use instead of the one chosen by tag
+        #[arg(long)]
+        template: Option<String>,
+    },
     /// Search through indexed papers
     Search {
         query: String,
@@ -28,62 +49,414 @@ enum Commands {
         #[arg(short, long, value_delimiter = ',', num_args = 1..)]
         tags: Option<Vec<String>>,
 
+        /// Only search papers with this reading status
+        #[arg(long, value_enum)]
+        status: Option<ReadingStatus>,
+
+        /// Only search papers rated at least this many stars
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+        min_rating: Option<u8>,
+
+        /// Only search papers in this collection, in collection order
+        #[arg(short, long)]
+        collection: Option<String>,
+
         /// Also search inside the PDF text
         #[arg(long)]
         pdf: bool,
+
+        /// Pick results from a list and act on them
+        #[arg(short, long)]
+        interactive: bool,
+    },
+    /// List papers in the library
+    List {
+        /// Filter by tags (comma-separated: --tags=math,physics)
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        tags: Option<Vec<String>>,
+
+        /// Only list papers with this reading status
+        #[arg(long, value_enum)]
+        status: Option<ReadingStatus>,
+
+        /// Only list papers rated at least this many stars
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+        min_rating: Option<u8>,
+
+        /// Only list papers in this collection, in collection order
+        #[arg(short, long)]
+        collection: Option<String>,
+    },
+    /// Set the reading status and priority of a paper
+    Status {
+        query: String,
+
+        /// New status (prompted for if neither a status nor a priority is given)
+        #[arg(value_enum)]
+        status: Option<ReadingStatus>,
+
+        /// Queue priority, higher is read first
+        #[arg(long, allow_negative_numbers = true)]
+        priority: Option<i64>,
     },
-    /// Remove a paper and its data
+    /// List papers marked to-read, by priority and age
+    Queue {
+        /// Filter by tags (comma-separated: --tags=math,physics)
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        tags: Option<Vec<String>>,
+
+        /// Only list papers rated at least this many stars
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+        min_rating: Option<u8>,
+
+        /// Only list papers in this collection
+        #[arg(short, long)]
+        collection: Option<String>,
+    },
+    /// Summarise time spent in `papr notes` per paper and tag
+    Log {
+        /// First day to include, as YYYY-MM-DD (defaults to a week ago)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+
+        /// Last day to include, as YYYY-MM-DD (defaults to today)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Rate a paper, star it, or note why it matters
+    Rate {
+        query: String,
+
+        /// Rating from 1 to 5 (everything is prompted for if no option is given)
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=5))]
+        rating: Option<u8>,
+
+        /// Add the paper to the starred papers
+        #[arg(long, conflicts_with = "unstar")]
+        star: bool,
+
+        /// Remove the paper from the starred papers
+        #[arg(long)]
+        unstar: bool,
+
+        /// A one-line note on why the paper matters
+        #[arg(long)]
+        why: Option<String>,
+    },
+    /// Move a paper and its data to the trash
     Remove { query: String },
+    /// Open a paper's PDF
+    Open {
+        query: String,
+
+        /// Page to open the PDF at
+        #[arg(long)]
+        page: Option<usize>,
+    },
     /// Compile and open the Typst summary
-    Notes { query: String },
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Notes {
+        #[command(subcommand)]
+        command: Option<NotesCommands>,
+
+        #[arg(required = true)]
+        query: Option<String>,
+    },
+    /// List the notes that mention a paper with @papr:<id>
+    Backlinks { query: String },
     /// Change the tags assigned to a paper
     Tag { query: String },
     /// Change the citation assigned to a paper
     Cite { query: String },
+    /// Attach an extra file to a paper, e.g. supplementary material or slides
+    Attach {
+        query: String,
+
+        /// Local file path or URL of the attachment
+        source: String,
+
+        /// What kind of attachment this is
+        #[arg(long, value_enum, default_value_t = AttachmentKind::Other)]
+        kind: AttachmentKind,
+    },
+    /// Rename a paper directory or move it to another directory
+    Mv {
+        query: String,
+
+        /// New path for the paper directory, or an existing directory to move it into
+        destination: PathBuf,
+    },
+    /// Download a paper's PDF again, keeping its notes and tags
+    Refetch { query: String },
+    /// Check arXiv papers for newer versions and download them
+    Outdated,
+    /// Find paper directories that are not in the database and register them
+    Scan {
+        /// Directory to scan (defaults to the current directory)
+        dir: Option<PathBuf>,
+
+        /// Notes template to use instead of the one chosen by tag
+        #[arg(long)]
+        template: Option<String>,
+    },
+    /// Check that the database and the paper directories agree
+    Doctor {
+        /// Repair the problems that can be fixed automatically
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Find papers that were added more than once and merge them
+    Dedupe,
+    /// Compile the notes of a tag or collection into one PDF
+    #[command(group = clap::ArgGroup::new("papers").required(true).args(["tags", "collection"]))]
+    Digest {
+        /// Include papers with these tags (comma-separated: --tags=math,physics)
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        tags: Option<Vec<String>>,
+
+        /// Include the papers of this collection, in collection order
+        #[arg(short, long)]
+        collection: Option<String>,
+
+        /// Output PDF, next to which the generated .typ and .bib are kept
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Manage ordered reading lists
+    Collection {
+        #[command(subcommand)]
+        command: CollectionCommands,
+    },
+    /// Manage removed papers
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum CollectionCommands {
+    /// List all collections
+    List,
+    /// Create an empty collection
+    Create { name: String },
+    /// Delete a collection, keeping its papers
+    Delete { name: String },
+    /// Add papers to the end of a collection
+    Add { name: String, query: String },
+    /// Remove papers from a collection
+    Remove { name: String },
+    /// Change the order of the papers in a collection
+    Reorder { name: String },
+    /// Show the papers in a collection, in order
+    Show { name: String },
+}
+
+#[derive(Subcommand)]
+enum NotesCommands {
+    /// Compile the notes of many papers without watching
+    #[command(group = clap::ArgGroup::new("papers").required(true).args(["tags", "all"]))]
+    Build {
+        /// Only build papers with these tags (comma-separated: --tags=math,physics)
+        #[arg(short, long, value_delimiter = ',', num_args = 1..)]
+        tags: Option<Vec<String>>,
+
+        /// Build every paper in the library
+        #[arg(long)]
+        all: bool,
+
+        /// Rebuild PDFs that are already up to date
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrashCommands {
+    /// List papers in the trash
+    List,
+    /// Move papers out of the trash back to their original location
+    Restore,
+    /// Permanently delete every paper in the trash
+    Empty,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
     let db_path = get_db_path(cli.global)?;
+    let trash_dir = get_trash_dir(cli.global)?;
 
     // Initialize DB
     println!("DB file at {:?}", db_path);
-    let db = Builder::new_local(db_path).build().await?;
+    let db = Builder::new_local(&db_path).build().await?;
     let conn = db.connect()?;
 
     // Initialize Schema
-    conn.execute_batch(
-        "CREATE TABLE IF NOT EXISTS papers (
-            id INTEGER PRIMARY KEY,
-            canonical_base_path TEXT NOT NULL UNIQUE,
-            url TEXT NOT NULL,
-            date_added TEXT NOT NULL,
-            citation TEXT NOT NULL
-        );
-        CREATE TABLE IF NOT EXISTS tags (
-            id INTEGER PRIMARY KEY,
-            name TEXT UNIQUE
-        );
-        CREATE TABLE IF NOT EXISTS paper_tags (
-            paper_id INTEGER,
-            tag_id INTEGER,
-            FOREIGN KEY(paper_id) REFERENCES papers(id) ON DELETE CASCADE,
-            FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
-        );",
-    )
-    .await
-    .context("Error initializing DB schema.")?;
+    init_schema(&conn).await?;
+
+    let config = Config::load()?;
+    let library = Library::open(&conn, &db_path, cli.global, &config).await?;
+    println!("Library root at {:?}", library.root());
+    purge_expired_trash(&conn, &library, config.trash.retention_days).await?;
+    let downloader = Downloader::new(&config.download)?;
 
     println!();
 
     match cli.command {
-        Commands::Add => handle_add(&conn).await?,
-        Commands::Search { query, tags, pdf } => handle_search(&conn, query, tags, pdf).await?,
-        Commands::Remove { query } => handle_remove(&conn, query).await?,
-        Commands::Notes { query } => handle_notes(&conn, query).await?,
-        Commands::Tag { query } => handle_retag(&conn, query).await?,
-        Commands::Cite { query } => handle_cite(&conn, query).await?,
+        Commands::Add {
+            file,
+            move_file,
+            template,
+        } => {
+            handle_add(
+                &conn,
+                &library,
+                &downloader,
+                &config,
+                file,
+                move_file,
+                template,
+            )
+            .await?
+        }
+        Commands::Search {
+            query,
+            tags,
+            status,
+            min_rating,
+            collection,
+            pdf,
+            interactive,
+        } => {
+            let filter = PaperFilter {
+                tags,
+                status,
+                min_rating,
+                collection,
+            };
+            handle_search(
+                &conn,
+                &library,
+                &config.viewer,
+                query,
+                filter,
+                pdf,
+                interactive,
+            )
+            .await?
+        }
+        Commands::List {
+            tags,
+            status,
+            min_rating,
+            collection,
+        } => {
+            let filter = PaperFilter {
+                tags,
+                status,
+                min_rating,
+                collection,
+            };
+            handle_list(&conn, &library, filter).await?
+        }
+        Commands::Status {
+            query,
+            status,
+            priority,
+        } => handle_status(&conn, &library, query, status, priority).await?,
+        Commands::Queue {
+            tags,
+            min_rating,
+            collection,
+        } => {
+            let filter = PaperFilter {
+                tags,
+                min_rating,
+                collection,
+                ..Default::default()
+            };
+            handle_queue(&conn, &library, filter).await?
+        }
+        Commands::Log { from, to } => handle_log(&conn, &library, from, to).await?,
+        Commands::Rate {
+            query,
+            rating,
+            star,
+            unstar,
+            why,
+        } => {
+            let starred = (star || unstar).then_some(star);
+            handle_rate(&conn, &library, query, rating, starred, why).await?
+        }
+        Commands::Remove { query } => handle_remove(&conn, &library, query, &trash_dir).await?,
+        Commands::Open { query, page } => {
+            handle_open(&conn, &library, &config.viewer, query, page).await?
+        }
+        Commands::Notes { command, query } => match (command, query) {
+            (
+                Some(NotesCommands::Build {
+                    tags,
+                    all: _,
+                    force,
+                }),
+                _,
+            ) => handle_notes_build(&conn, &library, tags, force).await?,
+            (None, Some(query)) => handle_notes(&conn, &library, &config.viewer, query).await?,
+            (None, None) => unreachable!("clap requires a query without a subcommand"),
+        },
+        Commands::Tag { query } => handle_retag(&conn, &library, query).await?,
+        Commands::Cite { query } => handle_cite(&conn, &library, query).await?,
+        Commands::Attach {
+            query,
+            source,
+            kind,
+        } => handle_attach(&conn, &library, &downloader, query, source, kind).await?,
+        Commands::Mv { query, destination } => {
+            handle_move(&conn, &library, query, destination).await?
+        }
+        Commands::Refetch { query } => handle_refetch(&conn, &library, &downloader, query).await?,
+        Commands::Outdated => handle_outdated(&conn, &library, &downloader, &config.arxiv).await?,
+        Commands::Scan { dir, template } => {
+            handle_scan(&conn, &library, &config.templates, dir, template).await?
+        }
+        Commands::Doctor { fix } => handle_doctor(&conn, &library, fix).await?,
+        Commands::Dedupe => handle_dedupe(&conn, &library, &trash_dir).await?,
+        Commands::Backlinks { query } => handle_backlinks(&conn, &library, query).await?,
+        Commands::Digest {
+            tags,
+            collection,
+            output,
+        } => {
+            let filter = PaperFilter {
+                tags,
+                collection,
+                ..Default::default()
+            };
+            handle_digest(&conn, &library, filter, output).await?
+        }
+        Commands::Collection { command } => match command {
+            CollectionCommands::List => handle_collection_list(&conn).await?,
+            CollectionCommands::Create { name } => handle_collection_create(&conn, name).await?,
+            CollectionCommands::Delete { name } => handle_collection_delete(&conn, name).await?,
+            CollectionCommands::Add { name, query } => {
+                handle_collection_add(&conn, &library, name, query).await?
+            }
+            CollectionCommands::Remove { name } => {
+                handle_collection_remove(&conn, &library, name).await?
+            }
+            CollectionCommands::Reorder { name } => {
+                handle_collection_reorder(&conn, &library, name).await?
+            }
+            CollectionCommands::Show { name } => {
+                handle_collection_show(&conn, &library, name).await?
+            }
+        },
+        Commands::Trash { command } => match command {
+            TrashCommands::List => handle_trash_list(&conn, &library).await?,
+            TrashCommands::Restore => handle_trash_restore(&conn, &library).await?,
+            TrashCommands::Empty => handle_trash_empty(&conn, &library).await?,
+        },
     }
 
     Ok(())
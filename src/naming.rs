@@ -0,0 +1,281 @@
+use anyhow::Result;
+use regex::Regex;
+use std::sync::LazyLock;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::NamingConfig;
+
+/// Words left out of `{short_title}`.
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "at", "by", "for", "from", "in", "of", "on", "the", "to", "with",
+];
+/// Number of words kept in `{short_title}`.
+const SHORT_TITLE_WORDS: usize = 4;
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(\w*)\}").unwrap());
+static YEAR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(19|20)\d{2}\b").unwrap());
+
+/// What a directory name can be built from.
+#[derive(Debug, Default)]
+pub struct NameParts {
+    pub title: String,
+    pub authors: Option<String>,
+    pub year: Option<String>,
+}
+
+impl NameParts {
+    /// Fill in the authors and year from a plain-text citation where they
+    /// are still missing. Citations usually start with the author list.
+    /// BibTeX entries are left alone, their first field is not the authors.
+    pub fn fill_from_citation(&mut self, citation: &str) {
+        if citation.trim_start().starts_with('@') {
+            return;
+        }
+        if self.year.is_none() {
+            self.year = YEAR.find(citation).map(|m| m.as_str().to_string());
+        }
+        if self.authors.is_none() {
+            let authors = citation.split(['(', '.']).next().unwrap_or_default().trim();
+            self.authors = (!authors.is_empty()).then(|| authors.to_string());
+        }
+    }
+
+    /// Family name of the first author, for both "Ada Lovelace, ..." and
+    /// "Lovelace, A. and ..." styles.
+    fn first_author(&self) -> Option<String> {
+        let authors = self.authors.as_deref()?;
+        let first = authors
+            .split([',', ';', '&'])
+            .flat_map(|s| s.split(" and "))
+            .map(str::trim)
+            .find(|s| !s.is_empty())?;
+        first.split_whitespace().last().map(str::to_string)
+    }
+
+    /// The title before any subtitle, without stopwords.
+    fn short_title(&self) -> String {
+        let main = self.title.split([':', '?', '!']).next().unwrap_or_default();
+        main.split_whitespace()
+            .filter(|w| !STOPWORDS.contains(&w.to_lowercase().as_str()))
+            .take(SHORT_TITLE_WORDS)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// A BibTeX-style key such as `vaswani2017attention`.
+    pub fn citation_key(&self) -> String {
+        let author = self.first_author().unwrap_or_default();
+        let short_title = self.short_title();
+        let word = short_title.split_whitespace().next().unwrap_or_default();
+        let key = [
+            author.as_str(),
+            self.year.as_deref().unwrap_or_default(),
+            word,
+        ]
+        .iter()
+        .map(|part| slugify(part, usize::MAX).replace(['_', '-'], ""))
+        .collect::<String>();
+        if key.is_empty() {
+            "paper".to_string()
+        } else {
+            key
+        }
+    }
+}
+
+/// ASCII approximation of a letter that has no decomposition into a base
+/// letter and accents.
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ß' => "ss",
+        'æ' | 'Æ' => "ae",
+        'œ' | 'Œ' => "oe",
+        'ø' | 'Ø' => "o",
+        'ł' | 'Ł' => "l",
+        'đ' | 'Đ' | 'ð' | 'Ð' => "d",
+        'þ' | 'Þ' => "th",
+        'ı' => "i",
+        _ => return None,
+    })
+}
+
+/// Turn arbitrary text into a lowercase ASCII name made of letters, digits,
+/// `-` and single `_` separators, cut at a word boundary to `max_length`.
+pub fn slugify(text: &str, max_length: usize) -> String {
+    let mut ascii = String::new();
+    for c in text.nfkd() {
+        if c.is_ascii() {
+            ascii.push(c);
+        } else if let Some(s) = transliterate(c) {
+            ascii.push_str(s);
+        } else if !c.is_alphanumeric() {
+            // Accents decompose into combining marks, which are dropped,
+            // everything else non-ASCII separates words
+            if !unicode_normalization::char::is_combining_mark(c) {
+                ascii.push(' ');
+            }
+        }
+    }
+
+    let mut slug = String::new();
+    for c in ascii.to_lowercase().chars() {
+        let joins_words = c == '-' && slug.ends_with(|c: char| c.is_ascii_alphanumeric());
+        if c.is_ascii_alphanumeric() || joins_words {
+            slug.push(c);
+        } else {
+            // A hyphen that does not join two words is just a separator
+            if slug.ends_with('-') {
+                slug.pop();
+            }
+            if !slug.is_empty() && !slug.ends_with('_') {
+                slug.push('_');
+            }
+        }
+    }
+    let mut slug = slug.trim_matches(['_', '-']).to_string();
+
+    if slug.len() > max_length {
+        let cut = slug[..max_length].rfind('_').unwrap_or(max_length);
+        slug.truncate(cut);
+        // Cutting inside a hyphenated word can leave a trailing hyphen
+        slug = slug.trim_end_matches(['_', '-']).to_string();
+    }
+    slug
+}
+
+/// Build a directory name from `config.pattern`. Placeholders without a
+/// value are dropped along with their separators.
+pub fn directory_name(parts: &NameParts, config: &NamingConfig) -> Result<String> {
+    let mut unknown = None;
+    let expanded =
+        PLACEHOLDER.replace_all(&config.pattern, |caps: &regex::Captures| match &caps[1] {
+            "title" => parts.title.clone(),
+            "short_title" => parts.short_title(),
+            "first_author" => parts.first_author().unwrap_or_default(),
+            "year" => parts.year.clone().unwrap_or_default(),
+            other => {
+                unknown = Some(other.to_string());
+                String::new()
+            }
+        });
+    if let Some(name) = unknown {
+        anyhow::bail!(
+            "Unknown placeholder {{{}}} in naming pattern '{}'. Use {{title}}, {{short_title}}, {{first_author}} or {{year}}.",
+            name,
+            config.pattern
+        );
+    }
+
+    let slug = slugify(&expanded, config.max_length);
+    Ok(if slug.is_empty() {
+        "paper".to_string()
+    } else {
+        slug
+    })
+}
+
+/// `name`, `name_2`, `name_3`, ... for resolving collisions.
+pub fn with_suffix(name: &str, attempt: u32) -> String {
+    if attempt <= 1 {
+        name.to_string()
+    } else {
+        format!("{}_{}", name, attempt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(title: &str, authors: Option<&str>, year: Option<&str>) -> NameParts {
+        NameParts {
+            title: title.to_string(),
+            authors: authors.map(str::to_string),
+            year: year.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn slugifies_accents_and_punctuation() {
+        assert_eq!(
+            slugify("Über die Straße: a Self-Attention Model!", 80),
+            "uber_die_strasse_a_self-attention_model"
+        );
+        assert_eq!(slugify("-- x -- y --", 80), "x_y");
+        assert_eq!(slugify("日本語", 80), "");
+    }
+
+    #[test]
+    fn slugify_cuts_at_word_boundaries() {
+        assert_eq!(slugify("attention is all you need", 15), "attention_is");
+        assert_eq!(slugify("transformers", 5), "trans");
+        assert_eq!(slugify("self-attention", 5), "self");
+    }
+
+    #[test]
+    fn fills_authors_and_year_from_plain_citations() {
+        let mut p = parts("Attention Is All You Need", None, None);
+        p.fill_from_citation("Vaswani, A. and Shazeer, N. (2017). Attention is all you need.");
+        assert_eq!(p.authors.as_deref(), Some("Vaswani, A"));
+        assert_eq!(p.year.as_deref(), Some("2017"));
+
+        let mut p = parts("A", Some("Known"), None);
+        p.fill_from_citation("Other (1999).");
+        assert_eq!(p.authors.as_deref(), Some("Known"));
+        assert_eq!(p.year.as_deref(), Some("1999"));
+    }
+
+    #[test]
+    fn ignores_bibtex_citations() {
+        let mut p = parts("A", None, None);
+        p.fill_from_citation("  @article{vaswani2017, year = {2017}}");
+        assert_eq!(p.authors, None);
+        assert_eq!(p.year, None);
+    }
+
+    #[test]
+    fn builds_citation_keys() {
+        let p = parts(
+            "The Attention Is All You Need",
+            Some("Ashish Vaswani, Noam Shazeer"),
+            Some("2017"),
+        );
+        assert_eq!(p.citation_key(), "vaswani2017attention");
+        let p = parts("On Graphs", Some("Lovelace, A. and Babbage, C."), None);
+        assert_eq!(p.citation_key(), "lovelacegraphs");
+        assert_eq!(parts("", None, None).citation_key(), "paper");
+    }
+
+    #[test]
+    fn expands_naming_patterns() {
+        let config = NamingConfig {
+            pattern: "{year}_{first_author}_{short_title}".to_string(),
+            max_length: 80,
+        };
+        let p = parts(
+            "Attention Is All You Need: Transformers",
+            Some("Ashish Vaswani"),
+            None,
+        );
+        assert_eq!(
+            directory_name(&p, &config).unwrap(),
+            "vaswani_attention_is_all_you"
+        );
+
+        let config = NamingConfig {
+            pattern: "{venue}".to_string(),
+            max_length: 80,
+        };
+        assert!(directory_name(&p, &config).is_err());
+        assert_eq!(
+            directory_name(&parts("", None, None), &NamingConfig::default()).unwrap(),
+            "paper"
+        );
+    }
+
+    #[test]
+    fn suffixes_later_attempts() {
+        assert_eq!(with_suffix("paper", 1), "paper");
+        assert_eq!(with_suffix("paper", 3), "paper_3");
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::Result;
+use lopdf::{Document, Object, decode_text_string};
+use std::path::Path;
+
+/// Bibliographic fields found in a PDF file's own metadata.
+#[derive(Debug, Default)]
+pub struct PdfMetadata {
+    pub title: Option<String>,
+    pub authors: Option<String>,
+    pub year: Option<String>,
+}
+
+impl PdfMetadata {
+    /// A plain-text citation prefilled from whatever fields are present.
+    pub fn citation(&self) -> Option<String> {
+        let authors = self.authors.as_deref()?;
+        let mut citation = authors.to_string();
+        if let Some(year) = &self.year {
+            citation.push_str(&format!(" ({})", year));
+        }
+        if let Some(title) = &self.title {
+            citation.push_str(&format!(". {}", title));
+        }
+        citation.push('.');
+        Some(citation)
+    }
+}
+
+fn info_string(doc: &Document, info: &lopdf::Dictionary, key: &[u8]) -> Option<String> {
+    let value = match info.get(key).ok()? {
+        Object::Reference(id) => doc.get_object(*id).ok()?,
+        obj => obj,
+    };
+    let text = decode_text_string(value).ok()?;
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// The text of every `<rdf:li>` inside the first `<{tag}>` element of an XMP packet.
+fn xmp_values(xml: &str, tag: &str) -> Vec<String> {
+    let Some(start) = xml.find(&format!("<{}", tag)) else {
+        return Vec::new();
+    };
+    let end = xml[start..]
+        .find(&format!("</{}>", tag))
+        .map_or(xml.len(), |i| start + i);
+
+    let mut values = Vec::new();
+    let mut rest = &xml[start..end];
+    while let Some(li) = rest.find("<rdf:li") {
+        rest = &rest[li..];
+        let (Some(open_end), Some(close)) = (rest.find('>'), rest.find("</rdf:li>")) else {
+            break;
+        };
+        if open_end < close {
+            let value = rest[open_end + 1..close].trim();
+            if !value.is_empty() {
+                values.push(
+                    value
+                        .replace("&lt;", "<")
+                        .replace("&gt;", ">")
+                        .replace("&quot;", "\"")
+                        .replace("&apos;", "'")
+                        .replace("&amp;", "&"),
+                );
+            }
+        }
+        rest = &rest[close + "</rdf:li>".len()..];
+    }
+    values
+}
+
+/// The four-digit year a date string starts with.
+fn year_prefix(date: &str) -> Option<String> {
+    let year = date.get(..4)?;
+    year.chars()
+        .all(|c| c.is_ascii_digit())
+        .then(|| year.to_string())
+}
+
+/// The XMP metadata packet referenced from the document catalog, if any.
+fn read_xmp(doc: &Document) -> Option<String> {
+    let metadata = match doc.catalog().ok()?.get(b"Metadata").ok()? {
+        Object::Reference(id) => doc.get_object(*id).ok()?,
+        obj => obj,
+    };
+    let stream = metadata.as_stream().ok()?;
+    let content = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+    String::from_utf8(content).ok()
+}
+
+/// Read the Info dictionary and XMP metadata of a PDF, preferring the Info
+/// dictionary. Missing or undecodable fields are `None`.
+pub fn read_pdf_metadata(pdf_path: &Path) -> Result<PdfMetadata> {
+    let doc = Document::load(pdf_path)?;
+    let mut meta = PdfMetadata::default();
+
+    let info = match doc.trailer.get(b"Info") {
+        Ok(Object::Reference(id)) => doc.get_dictionary(*id).ok(),
+        Ok(Object::Dictionary(dict)) => Some(dict),
+        _ => None,
+    };
+    if let Some(info) = info {
+        meta.title = info_string(&doc, info, b"Title");
+        meta.authors = info_string(&doc, info, b"Author");
+        // Dates look like `D:20240131120000+00'00'`
+        meta.year = info_string(&doc, info, b"CreationDate")
+            .and_then(|date| year_prefix(date.trim_start_matches("D:")));
+    }
+
+    if let Some(xml) = read_xmp(&doc) {
+        if meta.title.is_none() {
+            meta.title = xmp_values(&xml, "dc:title").into_iter().next();
+        }
+        if meta.authors.is_none() {
+            let creators = xmp_values(&xml, "dc:creator");
+            meta.authors = (!creators.is_empty()).then(|| creators.join(", "));
+        }
+        if meta.year.is_none() {
+            // Dates look like `2024-01-31` or `2024-01-31T12:00:00Z`
+            meta.year = xmp_values(&xml, "dc:date")
+                .into_iter()
+                .next()
+                .and_then(|date| year_prefix(&date));
+        }
+    }
+
+    Ok(meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{Stream, dictionary};
+
+    #[test]
+    fn reads_list_items_of_the_requested_tag() {
+        let xml = r#"<rdf:Description>
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default"> Q&amp;A &lt;Nets&gt; </rdf:li></rdf:Alt></dc:title>
+            <dc:creator><rdf:Seq><rdf:li>Ada</rdf:li><rdf:li></rdf:li><rdf:li>Alan</rdf:li></rdf:Seq></dc:creator>
+        </rdf:Description>"#;
+        assert_eq!(xmp_values(xml, "dc:title"), ["Q&A <Nets>"]);
+        assert_eq!(xmp_values(xml, "dc:creator"), ["Ada", "Alan"]);
+        assert!(xmp_values(xml, "dc:date").is_empty());
+    }
+
+    #[test]
+    fn citation_needs_authors() {
+        let mut meta = PdfMetadata {
+            title: Some("Title".to_string()),
+            authors: None,
+            year: Some("2020".to_string()),
+        };
+        assert_eq!(meta.citation(), None);
+        meta.authors = Some("Ada, Alan".to_string());
+        assert_eq!(meta.citation().as_deref(), Some("Ada, Alan (2020). Title."));
+        meta.year = None;
+        meta.title = None;
+        assert_eq!(meta.citation().as_deref(), Some("Ada, Alan."));
+    }
+
+    #[test]
+    fn prefers_info_dictionary_and_falls_back_to_xmp() {
+        let mut doc = Document::with_version("1.5");
+        let xmp = r#"<dc:title><rdf:Alt><rdf:li>XMP title</rdf:li></rdf:Alt></dc:title>
+            <dc:creator><rdf:Seq><rdf:li>Ada</rdf:li><rdf:li>Alan</rdf:li></rdf:Seq></dc:creator>
+            <dc:date><rdf:Seq><rdf:li>2019-05-01</rdf:li></rdf:Seq></dc:date>"#;
+        let metadata = doc.add_object(Stream::new(dictionary! {}, xmp.as_bytes().to_vec()));
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Metadata" => metadata });
+        doc.trailer.set("Root", catalog);
+        let info = doc.add_object(dictionary! {
+            "Title" => Object::string_literal("Info title"),
+            "CreationDate" => Object::string_literal("D:notadate"),
+        });
+        doc.trailer.set("Info", info);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("paper.pdf");
+        doc.save(&path).unwrap();
+
+        let meta = read_pdf_metadata(&path).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Info title"));
+        assert_eq!(meta.authors.as_deref(), Some("Ada, Alan"));
+        // An unparseable Info date falls back to XMP's
+        assert_eq!(meta.year.as_deref(), Some("2019"));
+    }
+}
@@ -0,0 +1,112 @@
+use anyhow::{Context, Result};
+use inquire::{Confirm, Select, Text};
+
+use crate::library::Library;
+use crate::search;
+
+/// `★★★☆☆` for a rating of 3.
+pub fn stars(rating: u8) -> String {
+    let rating = rating.min(5) as usize;
+    format!("{}{}", "★".repeat(rating), "☆".repeat(5 - rating))
+}
+
+async fn get_rating(
+    conn: &libsql::Connection,
+    paper_id: u32,
+) -> Result<(Option<u8>, bool, Option<String>)> {
+    let mut rows = conn
+        .query(
+            "SELECT rating, starred, relevance FROM papers WHERE id = ?1",
+            [paper_id],
+        )
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok((
+            row.get::<Option<u32>>(0)?.map(|r| r as u8),
+            row.get::<u32>(1)? != 0,
+            row.get(2)?,
+        )),
+        None => anyhow::bail!("Paper ID {} not found in database.", paper_id),
+    }
+}
+
+pub async fn handle_rate(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+    rating: Option<u8>,
+    starred: Option<bool>,
+    relevance: Option<String>,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper to rate:", matching_papers)
+        .prompt()
+        .context("No paper selected for rating.")?;
+    let id = paper_selection.id;
+
+    let (current_rating, current_starred, current_relevance) = get_rating(conn, id).await?;
+
+    // Ask for everything when nothing was given on the command line
+    let (rating, starred, relevance) =
+        if rating.is_none() && starred.is_none() && relevance.is_none() {
+            let options = (1..=5).rev().map(stars).collect::<Vec<_>>();
+            let cursor = current_rating.map_or(0, |r| 5 - r.clamp(1, 5) as usize);
+            let rating = Select::new("Rating:", options)
+                .with_starting_cursor(cursor)
+                .prompt_skippable()?
+                .map(|s| s.chars().filter(|c| *c == '★').count() as u8);
+            let starred = Confirm::new("Starred?")
+                .with_default(current_starred)
+                .prompt()?;
+            let relevance = Text::new("Why it matters (optional):")
+                .with_initial_value(current_relevance.as_deref().unwrap_or_default())
+                .prompt_skippable()
+                .context("Invalid text.")?;
+            // Esc keeps the current values, an emptied text clears it
+            (
+                rating.or(current_rating),
+                starred,
+                relevance.or(current_relevance),
+            )
+        } else {
+            (
+                rating.or(current_rating),
+                starred.unwrap_or(current_starred),
+                relevance.or(current_relevance),
+            )
+        };
+    let relevance = relevance.filter(|r| !r.trim().is_empty());
+
+    conn.execute(
+        "UPDATE papers SET rating = ?1, starred = ?2, relevance = ?3 WHERE id = ?4",
+        (rating.map(u32::from), starred as u32, relevance.clone(), id),
+    )
+    .await
+    .context("Error updating paper rating.")?;
+
+    println!(
+        "{}: {}{}",
+        paper_selection.canonical_base_path,
+        rating.map_or("unrated".to_string(), stars),
+        if starred { ", starred" } else { "" }
+    );
+    if let Some(relevance) = relevance {
+        println!("Why it matters: {}", relevance);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_five_stars() {
+        assert_eq!(stars(0), "☆☆☆☆☆");
+        assert_eq!(stars(3), "★★★☆☆");
+        assert_eq!(stars(9), "★★★★★");
+    }
+}
@@ -0,0 +1,284 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use inquire::{MultiSelect, Select, Text};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::TemplateConfig;
+use crate::dedupe::{self, DuplicateAction, Fingerprint};
+use crate::doctor::{find_untracked_dirs, get_known_dirs, looks_like_paper_dir};
+use crate::library::Library;
+use crate::naming::NameParts;
+use crate::pdfmeta::read_pdf_metadata;
+use crate::templates::{self, NoteValues};
+use crate::{get_tag_selections, tag_paper};
+
+/// How deep below the scanned directory to look for paper directories.
+const MAX_SCAN_DEPTH: usize = 3;
+
+fn find_pdfs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut pdfs = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+        })
+        .collect::<Vec<_>>();
+    pdfs.sort();
+    pdfs
+}
+
+/// The PDF that is the paper itself, asking when there are several.
+fn choose_pdf(dir: &Path) -> Result<Option<PathBuf>> {
+    let mut pdfs = find_pdfs(dir);
+    if pdfs.len() <= 1 {
+        return Ok(pdfs.pop());
+    }
+    let names = pdfs
+        .iter()
+        .map(|p| {
+            p.file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    let selection = Select::new(
+        "Several PDFs found. Which one is the paper? (Esc to keep them as they are)",
+        names,
+    )
+    .raw_prompt_skippable()?;
+    Ok(selection.map(|s| pdfs.swap_remove(s.index)))
+}
+
+/// Title from the PDF metadata if there is one, otherwise from the directory name.
+fn infer_title(dir: &Path, pdf_path: Option<&Path>) -> String {
+    pdf_path
+        .and_then(|p| read_pdf_metadata(p).ok())
+        .and_then(|meta| meta.title)
+        .unwrap_or_else(|| {
+            dir.file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .replace(['_', '-'], " ")
+        })
+}
+
+async fn register_dir(
+    conn: &libsql::Connection,
+    library: &Library,
+    template_config: &TemplateConfig,
+    template: Option<String>,
+    dir: &Path,
+) -> Result<()> {
+    println!("\nRegistering {}", dir.display());
+
+    // papr expects the paper itself to be called `paper.pdf`, any other PDF
+    // is only renamed once the user has decided to add the paper
+    let paper_pdf = dir.join("paper.pdf");
+    let pdf_path = if paper_pdf.exists() {
+        Some(paper_pdf.clone())
+    } else {
+        choose_pdf(dir)?
+    };
+
+    let title = Text::new("Paper title:")
+        .with_initial_value(&infer_title(dir, pdf_path.as_deref()))
+        .prompt()
+        .context("Invalid title.")?;
+    let url = Text::new("Paper PDF URL (optional):")
+        .prompt_skippable()
+        .context("Invalid URL.")?
+        .unwrap_or_default();
+    let tag_names = get_tag_selections(conn).await?;
+
+    let fingerprint = Fingerprint::new(&title, &url, "", pdf_path.as_deref())?;
+    let duplicates = dedupe::find_duplicates(conn, library, &fingerprint).await?;
+    match dedupe::prompt_duplicate_action(duplicates)? {
+        (DuplicateAction::Merge, Some(target_id)) => {
+            dedupe::merge_into(conn, target_id, &url, "", tag_names).await?;
+            println!(
+                "Merged tags into the existing paper, {} was left untracked.",
+                dir.display()
+            );
+            return Ok(());
+        }
+        (DuplicateAction::Skip, _) => {
+            println!("Skipped {}.", dir.display());
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let metadata = pdf_path
+        .as_deref()
+        .and_then(|p| read_pdf_metadata(p).ok())
+        .unwrap_or_default();
+    if let Some(found) = pdf_path.as_deref()
+        && found != paper_pdf
+    {
+        fs::rename(found, &paper_pdf)
+            .with_context(|| format!("Error renaming {:?} to paper.pdf.", found))?;
+        println!("Renamed {} to paper.pdf", found.display());
+    }
+    let main_typ = dir.join("summary").join("main.typ");
+    if !main_typ.exists() {
+        let template_name = templates::choose_template(template_config, template, &tag_names);
+        let template = templates::load_template(&template_name)?;
+        let parts = NameParts {
+            title: title.clone(),
+            authors: metadata.authors.clone(),
+            year: metadata.year.clone(),
+        };
+        let notes = templates::render(
+            &template,
+            &NoteValues {
+                parts: &parts,
+                url: &url,
+                tag_names: &tag_names,
+            },
+        );
+        fs::create_dir_all(dir.join("summary")).context("Error creating summary directory")?;
+        fs::write(&main_typ, notes)?;
+        println!("Created {}", main_typ.display());
+    }
+
+    let tx = conn.transaction().await?;
+    tx.execute(
+        "INSERT INTO papers (canonical_base_path, url, date_added, citation, title, authors, year)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        (
+            library.to_stored(dir)?,
+            url,
+            Local::now().format("%Y-%m-%d").to_string(),
+            String::new(),
+            title.clone(),
+            metadata.authors,
+            metadata.year,
+        ),
+    )
+    .await
+    .context("Error updating papers table.")?;
+    let paper_id = tx.last_insert_rowid() as u32;
+    tag_paper(&tx, paper_id, tag_names).await?;
+    dedupe::store_fingerprint(&tx, paper_id, &fingerprint).await?;
+    tx.commit().await?;
+
+    println!("Added '{}' to your library!", title);
+    Ok(())
+}
+
+pub async fn handle_scan(
+    conn: &libsql::Connection,
+    library: &Library,
+    template_config: &TemplateConfig,
+    dir: Option<PathBuf>,
+    template: Option<String>,
+) -> Result<()> {
+    let dir = dir.unwrap_or_else(|| PathBuf::from("."));
+    let dir = fs::canonicalize(&dir).with_context(|| format!("Cannot scan {:?}.", dir))?;
+
+    let known = get_known_dirs(conn, library).await?;
+    let mut candidates = Vec::new();
+    find_untracked_dirs(
+        &dir,
+        &known,
+        MAX_SCAN_DEPTH,
+        &|p| looks_like_paper_dir(p) || !find_pdfs(p).is_empty(),
+        &mut candidates,
+    )?;
+
+    if candidates.is_empty() {
+        println!("No untracked paper directories found in {}.", dir.display());
+        return Ok(());
+    }
+
+    let options = candidates
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>();
+    let selections = MultiSelect::new(
+        "Select directories to add (Space to toggle, Enter to confirm):",
+        options,
+    )
+    .prompt()
+    .context("No directories selected.")?;
+
+    for selection in selections {
+        register_dir(
+            conn,
+            library,
+            template_config,
+            template.clone(),
+            Path::new(&selection),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn finds_pdfs_in_name_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.PDF", "a.pdf", "notes.txt"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("c.pdf")).unwrap();
+        assert_eq!(
+            find_pdfs(dir.path()),
+            [dir.path().join("a.pdf"), dir.path().join("b.PDF")]
+        );
+        assert!(find_pdfs(&dir.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn infers_title_from_directory_name() {
+        assert_eq!(
+            infer_title(Path::new("/papers/deep_residual-learning"), None),
+            "deep residual learning"
+        );
+    }
+
+    #[test]
+    fn finds_untracked_directories_with_pdfs() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["known", "loose", "nested/deeper", "empty", ".hidden"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        for pdf in [
+            "known/paper.pdf",
+            "loose/x.pdf",
+            "nested/deeper/y.pdf",
+            ".hidden/z.pdf",
+        ] {
+            fs::write(root.path().join(pdf), "").unwrap();
+        }
+        let known = HashSet::from([root.path().join("known")]);
+
+        let mut found = Vec::new();
+        find_untracked_dirs(
+            root.path(),
+            &known,
+            MAX_SCAN_DEPTH,
+            &|p| looks_like_paper_dir(p) || !find_pdfs(p).is_empty(),
+            &mut found,
+        )
+        .unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            [root.path().join("loose"), root.path().join("nested/deeper")]
+        );
+    }
+}
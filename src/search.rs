@@ -5,15 +5,25 @@ use nucleo_matcher::{
     pattern::{Atom, AtomKind, CaseMatching, Normalization},
 };
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::attachments::get_attachment_pdfs;
+use crate::collections::get_collection_id;
+use crate::library::Library;
+use crate::links;
+use crate::rating::stars;
+use crate::status::ReadingStatus;
+
 #[derive(Debug)]
 pub struct PaperMatch {
     pub id: u32,
     pub canonical_base_path: String,
     url: String,
     score: u32,
+    rating: Option<u8>,
+    starred: bool,
+    relevance: Option<String>,
 }
 
 impl fmt::Display for PaperMatch {
@@ -22,16 +32,33 @@ impl fmt::Display for PaperMatch {
             f,
             "Path: {}\nURL: {}\nID: {}\nScore: {}",
             self.canonical_base_path, self.url, self.id, self.score
-        )
+        )?;
+        if self.rating.is_some() || self.starred {
+            write!(
+                f,
+                "\nRating: {}{}",
+                self.rating.map_or("unrated".to_string(), stars),
+                if self.starred { " (starred)" } else { "" }
+            )?;
+        }
+        if let Some(relevance) = &self.relevance {
+            write!(f, "\nWhy it matters: {}", relevance)?;
+        }
+        Ok(())
     }
 }
 
 pub async fn fuzzy_search_papers(
     conn: &libsql::Connection,
+    library: &Library,
     query: &str,
 ) -> Result<Vec<PaperMatch>> {
     let mut rows = conn
-        .query("SELECT id, canonical_base_path, url FROM papers", ())
+        .query(
+            "SELECT id, canonical_base_path, url, rating, starred, relevance FROM papers
+             WHERE deleted_at IS NULL",
+            (),
+        )
         .await?;
 
     let needle = Atom::new(
@@ -46,8 +73,11 @@ pub async fn fuzzy_search_papers(
     let mut res = Vec::new();
     while let Some(row) = rows.next().await? {
         let id: u32 = row.get(0)?;
-        let canonical_base_path: String = row.get(1)?;
+        let canonical_base_path = library.resolve_string(&row.get::<String>(1)?);
         let url: String = row.get(2)?;
+        let rating = row.get::<Option<u32>>(3)?.map(|r| r as u8);
+        let starred = row.get::<u32>(4)? != 0;
+        let relevance: Option<String> = row.get(5)?;
 
         // Extract the folder name (the paper title) from the path
         let title = Path::new(&canonical_base_path)
@@ -66,62 +96,131 @@ pub async fn fuzzy_search_papers(
                     canonical_base_path,
                     url,
                     score: (score as u32),
+                    rating,
+                    starred,
+                    relevance,
                 });
             }
         }
     }
 
+    // Best match first, with rated and starred papers breaking ties
+    res.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(b.rating.cmp(&a.rating))
+            .then(b.starred.cmp(&a.starred))
+    });
+
     Ok(res)
 }
 
 #[derive(Debug)]
 pub struct PdfMatch {
+    pub paper_id: u32,
     pub canonical_path: String,
+    /// `paper.pdf`, or the attachment the hit came from
+    pub source: String,
+    pub path: PathBuf,
     pub page: usize,
     pub excerpt: String,
 }
 
-async fn filter_tagged_papers(
+/// Restricts which papers a search or listing looks at.
+#[derive(Debug, Default)]
+pub struct PaperFilter {
+    /// Papers must have every one of these tags
+    pub tags: Option<Vec<String>>,
+    pub status: Option<ReadingStatus>,
+    pub min_rating: Option<u8>,
+    /// Papers must be in this collection, and are returned in its order
+    pub collection: Option<String>,
+}
+
+pub struct FilteredPaper {
+    pub id: u32,
+    pub canonical_base_path: String,
+    pub status: String,
+    pub priority: i64,
+    pub date_added: String,
+    pub rating: Option<u8>,
+    pub starred: bool,
+}
+
+pub async fn filter_papers(
     conn: &libsql::Connection,
-    tags: Option<Vec<String>>,
-) -> libsql::Result<libsql::Rows> {
-    match tags {
-        Some(t_list) if !t_list.is_empty() => {
-            // Create a string of placeholders: "?, ?, ?"
-            let placeholders = t_list.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
-
-            let sql = format!(
-                "SELECT p.canonical_base_path
-                 FROM papers p
-                 JOIN paper_tags pt ON p.id = pt.paper_id
-                 JOIN tags t ON pt.tag_id = t.id
-                 WHERE t.name IN ({})
-                 GROUP BY p.id
-                 HAVING COUNT(DISTINCT t.name) = ?",
-                placeholders
-            );
-
-            let mut params: Vec<libsql::Value> = t_list.iter().cloned().map(Into::into).collect();
-
-            // Add the tag count as the final parameter
-            params.push((t_list.len() as i64).into());
-
-            conn.query(&sql, params).await
-        }
-        _ => {
-            // If no tags provided, fetch all papers
-            conn.query("SELECT canonical_base_path FROM papers", ())
-                .await
-        }
+    library: &Library,
+    filter: &PaperFilter,
+) -> Result<Vec<FilteredPaper>> {
+    let mut sql =
+        "SELECT p.canonical_base_path, p.id, p.status, p.priority, p.date_added, p.rating, p.starred
+         FROM papers p".to_string();
+    let mut conditions = vec!["p.deleted_at IS NULL".to_string()];
+    let mut params: Vec<libsql::Value> = Vec::new();
+
+    if let Some(status) = filter.status {
+        conditions.push("p.status = ?".to_string());
+        params.push(status.as_str().into());
+    }
+    if let Some(min_rating) = filter.min_rating {
+        conditions.push("p.rating >= ?".to_string());
+        params.push(i64::from(min_rating).into());
+    }
+
+    if let Some(collection) = &filter.collection {
+        let collection_id = get_collection_id(conn, collection).await?;
+        sql.push_str(" JOIN collection_papers cp ON p.id = cp.paper_id");
+        conditions.push("cp.collection_id = ?".to_string());
+        params.push(collection_id.into());
+    }
+
+    let tags = filter.tags.as_deref().unwrap_or_default();
+    if !tags.is_empty() {
+        sql.push_str(
+            " JOIN paper_tags pt ON p.id = pt.paper_id
+              JOIN tags t ON pt.tag_id = t.id",
+        );
+        // Create a string of placeholders: "?, ?, ?"
+        let placeholders = tags.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        conditions.push(format!("t.name IN ({})", placeholders));
+        params.extend(tags.iter().cloned().map(Into::into));
+    }
+
+    sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+    if !tags.is_empty() {
+        // Only papers matching every tag
+        sql.push_str(" GROUP BY p.id HAVING COUNT(DISTINCT t.name) = ?");
+        params.push((tags.len() as i64).into());
     }
+    if filter.collection.is_some() {
+        sql.push_str(" ORDER BY cp.position");
+    } else {
+        sql.push_str(" ORDER BY p.id");
+    }
+
+    let mut rows = conn.query(&sql, params).await?;
+    let mut papers = Vec::new();
+    while let Some(row) = rows.next().await? {
+        papers.push(FilteredPaper {
+            canonical_base_path: library.resolve_string(&row.get::<String>(0)?),
+            id: row.get(1)?,
+            status: row.get(2)?,
+            priority: row.get(3)?,
+            date_added: row.get(4)?,
+            rating: row.get::<Option<u32>>(5)?.map(|r| r as u8),
+            starred: row.get::<u32>(6)? != 0,
+        });
+    }
+    Ok(papers)
 }
 
 pub async fn fuzzy_search_pdfs(
     conn: &libsql::Connection,
+    library: &Library,
     query: &str,
-    tags: Option<Vec<String>>,
+    filter: &PaperFilter,
 ) -> Result<Vec<PdfMatch>> {
-    let mut rows = filter_tagged_papers(conn, tags).await?;
+    let papers = filter_papers(conn, library, filter).await?;
 
     let mut all_matches = Vec::new();
     let mut matcher = Nucleo::new(Config::DEFAULT, Arc::new(|| {}), None, 1);
@@ -134,29 +233,52 @@ pub async fn fuzzy_search_pdfs(
         false,
     );
 
-    while let Some(row) = rows.next().await? {
-        let base_path_str: String = row.get(0)?;
+    for paper in papers {
+        let base_path_str = paper.canonical_base_path;
+        let paper_id = paper.id;
         let base_path = Path::new(&base_path_str);
         let pdf_path = base_path.join("paper.pdf");
 
-        if !pdf_path.exists() {
-            continue;
+        let mut sources = Vec::new();
+        if pdf_path.exists() {
+            sources.push(("paper.pdf".to_string(), pdf_path, true));
         }
-
-        for (i, page_text) in pdf_extract::extract_text_by_pages(pdf_path)?
-            .into_iter()
-            .enumerate()
-        {
-            if page_text.trim().is_empty() {
-                continue;
+        for attachment in get_attachment_pdfs(conn, paper_id, base_path).await? {
+            if attachment.path.exists() {
+                sources.push((attachment.label, attachment.path, false));
             }
+        }
 
-            injector.push(
-                (page_text, i, base_path_str.clone()),
-                |haystack, columns| {
-                    columns[0] = Utf32String::from(haystack.0.as_str());
-                },
-            );
+        for (label, path, is_paper) in sources {
+            let pages = match pdf_extract::extract_text_by_pages(&path) {
+                Ok(pages) => pages,
+                Err(e) if is_paper => return Err(e.into()),
+                // A broken attachment should not stop the whole search
+                Err(e) => {
+                    eprintln!("Skipping unreadable attachment {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            for (i, page_text) in pages.into_iter().enumerate() {
+                if page_text.trim().is_empty() {
+                    continue;
+                }
+
+                injector.push(
+                    (
+                        page_text,
+                        i,
+                        base_path_str.clone(),
+                        label.clone(),
+                        path.clone(),
+                        paper_id,
+                    ),
+                    |haystack, columns| {
+                        columns[0] = Utf32String::from(haystack.0.as_str());
+                    },
+                );
+            }
         }
     }
 
@@ -168,7 +290,10 @@ pub async fn fuzzy_search_pdfs(
         let excerpt = matched_item.data.0.chars().take(120).collect::<String>();
 
         all_matches.push(PdfMatch {
+            paper_id: matched_item.data.5,
             canonical_path: matched_item.data.2.clone(),
+            source: matched_item.data.3.clone(),
+            path: matched_item.data.4.clone(),
             page: matched_item.data.1 + 1, // 1-indexed for humans
             excerpt: format!("{}...", excerpt.trim().replace('\n', " (new line) ")),
         });
@@ -178,17 +303,40 @@ pub async fn fuzzy_search_pdfs(
 }
 
 pub struct TypstMatch {
+    pub paper_id: u32,
     pub canonical_path: String,
+    /// The `.typ` file the hit came from
+    pub path: PathBuf,
+    /// First line of the matching paragraph
     pub line_number: usize,
     pub excerpt: String,
 }
 
+/// Chunk notes by paragraph (double newline) to provide context, pairing
+/// each non-blank paragraph with the 1-indexed line its text starts on.
+fn paragraphs(content: &str) -> Vec<(usize, &str)> {
+    let mut chunks = Vec::new();
+    let mut line = 1;
+    for chunk in content.split("\n\n") {
+        let chunk_line = line;
+        line += chunk.matches('\n').count() + 2;
+        if chunk.trim().is_empty() {
+            continue;
+        }
+        // Point at the paragraph text rather than blank lines before it
+        let chunk_line = chunk_line + chunk.len() - chunk.trim_start_matches('\n').len();
+        chunks.push((chunk_line, chunk));
+    }
+    chunks
+}
+
 pub async fn fuzzy_search_typst(
     conn: &libsql::Connection,
+    library: &Library,
     query: &str,
-    tags: Option<Vec<String>>,
+    filter: &PaperFilter,
 ) -> Result<Vec<TypstMatch>> {
-    let mut rows = filter_tagged_papers(conn, tags).await?;
+    let papers = filter_papers(conn, library, filter).await?;
 
     let mut all_matches = Vec::new();
     let mut matcher = Nucleo::new(Config::DEFAULT, Arc::new(|| {}), None, 1);
@@ -202,31 +350,40 @@ pub async fn fuzzy_search_typst(
         false,
     );
 
-    while let Some(row) = rows.next().await? {
-        let base_path_str: String = row.get(0)?;
+    for paper in papers {
+        let base_path_str = paper.canonical_base_path;
         let base_path = Path::new(&base_path_str);
         let summary_path = base_path.join("summary");
 
         if !summary_path.exists() {
+            links::store_links(conn, paper.id, &[]).await?;
             continue;
         }
 
-        // Walk the summary directory for any .typ files
+        // Walk the summary directory for any .typ files, refreshing the
+        // link index on the way since every note is read anyway
+        let mut note_links = Vec::new();
         for entry in std::fs::read_dir(summary_path)? {
             let entry = entry?;
             let path = entry.path();
 
-            if path.extension().is_some_and(|ext| ext == "typ") {
+            if links::is_note_file(&path) {
                 let content = std::fs::read_to_string(&path)?;
+                let file_name = path
+                    .file_name()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or_default();
+                note_links.extend(links::find_links(file_name, &content));
 
-                // Chunk by paragraph (double newline) to provide context
-                for (i, chunk) in content.split("\n\n").enumerate() {
-                    if chunk.trim().is_empty() {
-                        continue;
-                    }
-
+                for (chunk_line, chunk) in paragraphs(&content) {
                     injector.push(
-                        (chunk.to_string(), i, base_path_str.clone()),
+                        (
+                            chunk.to_string(),
+                            chunk_line,
+                            base_path_str.clone(),
+                            path.clone(),
+                            paper.id,
+                        ),
                         |haystack, columns| {
                             columns[0] = Utf32String::from(haystack.0.as_str());
                         },
@@ -234,6 +391,7 @@ pub async fn fuzzy_search_typst(
                 }
             }
         }
+        links::store_links(conn, paper.id, &note_links).await?;
     }
 
     // High timeout to ensure processing completes
@@ -245,12 +403,38 @@ pub async fn fuzzy_search_typst(
         let excerpt = text.chars().take(120).collect::<String>();
 
         all_matches.push(TypstMatch {
+            paper_id: matched_item.data.4,
             canonical_path: matched_item.data.2.clone(),
-            // Using paragraph index as "place"
-            line_number: matched_item.data.1 + 1,
+            path: matched_item.data.3.clone(),
+            line_number: matched_item.data.1,
             excerpt: format!("{}...", excerpt.trim().replace('\n', " ")),
         });
     }
 
     Ok(all_matches)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_start_at_their_first_text_line() {
+        let content = "= Title\n\nFirst para\nspans two\n\n\n\nAfter gap\n\n  \n\nLast";
+        assert_eq!(
+            paragraphs(content),
+            vec![
+                (1, "= Title"),
+                (3, "First para\nspans two"),
+                (8, "After gap"),
+                (12, "Last"),
+            ]
+        );
+    }
+
+    #[test]
+    fn paragraphs_of_blank_content_are_empty() {
+        assert!(paragraphs("").is_empty());
+        assert!(paragraphs("\n\n\n").is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::Path;
+
+use crate::db;
+use crate::library::Library;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// Days covered by `papr log` when no start date is given.
+const DEFAULT_LOG_DAYS: i64 = 7;
+
+/// Record the start of a notes session. The session stays open, and is not
+/// counted by `papr log`, until `end_session` is called.
+pub async fn start_session(conn: &libsql::Connection, paper_id: u32) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO sessions (paper_id, started_at) VALUES (?1, ?2)",
+        (paper_id, Local::now().format(TIMESTAMP_FORMAT).to_string()),
+    )
+    .await
+    .context("Error recording session start.")?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub async fn end_session(conn: &libsql::Connection, session_id: i64) -> Result<Duration> {
+    let ended_at = Local::now().naive_local();
+    conn.execute(
+        "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
+        (ended_at.format(TIMESTAMP_FORMAT).to_string(), session_id),
+    )
+    .await
+    .context("Error recording session end.")?;
+
+    let mut rows = conn
+        .query(
+            "SELECT started_at FROM sessions WHERE id = ?1",
+            [session_id],
+        )
+        .await?;
+    let started_at = match rows.next().await? {
+        Some(row) => NaiveDateTime::parse_from_str(&row.get::<String>(0)?, TIMESTAMP_FORMAT)?,
+        None => anyhow::bail!("Session {} not found in database.", session_id),
+    };
+    Ok(ended_at - started_at)
+}
+
+/// `1h 05m`, or `12m` under an hour.
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes();
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+#[derive(Default)]
+struct Total {
+    duration: Duration,
+    sessions: u32,
+}
+
+fn add_session(totals: &mut HashMap<String, Total>, key: String, duration: Duration) {
+    let total = totals.entry(key).or_default();
+    total.duration += duration;
+    total.sessions += 1;
+}
+
+fn print_totals(heading: &str, totals: HashMap<String, Total>) {
+    let mut totals = totals.into_iter().collect::<Vec<_>>();
+    totals.sort_by(|a, b| b.1.duration.cmp(&a.1.duration).then(a.0.cmp(&b.0)));
+
+    println!("\n{}:", heading);
+    for (name, total) in totals {
+        println!(
+            "  {:>8}  {} ({} session(s))",
+            format_duration(total.duration),
+            name,
+            total.sessions
+        );
+    }
+}
+
+pub async fn handle_log(
+    conn: &libsql::Connection,
+    library: &Library,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Result<()> {
+    let to = to.unwrap_or_else(|| Local::now().date_naive());
+    let from = from.unwrap_or(to - Duration::days(DEFAULT_LOG_DAYS - 1));
+    if from > to {
+        anyhow::bail!("The start date {} is after the end date {}.", from, to);
+    }
+
+    // Timestamps sort as text, so the range is everything started on
+    // `from` up to the end of `to`
+    let mut rows = conn
+        .query(
+            "SELECT s.paper_id, p.canonical_base_path, s.started_at, s.ended_at
+             FROM sessions s JOIN papers p ON p.id = s.paper_id
+             WHERE s.started_at >= ?1 AND s.started_at < ?2",
+            (
+                from.format("%Y-%m-%d").to_string(),
+                (to + Duration::days(1)).format("%Y-%m-%d").to_string(),
+            ),
+        )
+        .await?;
+
+    let mut by_paper: HashMap<u32, Total> = HashMap::new();
+    let mut by_tag: HashMap<String, Total> = HashMap::new();
+    let mut total = Total::default();
+    let mut open = 0;
+    let mut paper_names: HashMap<u32, String> = HashMap::new();
+    let mut paper_tags: HashMap<u32, Vec<String>> = HashMap::new();
+    while let Some(row) = rows.next().await? {
+        let paper_id: u32 = row.get(0)?;
+        // Still running, or papr was killed before it could record the end
+        let Some(ended_at) = row.get::<Option<String>>(3)? else {
+            open += 1;
+            continue;
+        };
+        let started_at = NaiveDateTime::parse_from_str(&row.get::<String>(2)?, TIMESTAMP_FORMAT)?;
+        let ended_at = NaiveDateTime::parse_from_str(&ended_at, TIMESTAMP_FORMAT)?;
+        let duration = (ended_at - started_at).max(Duration::zero());
+
+        if let Entry::Vacant(entry) = paper_tags.entry(paper_id) {
+            entry.insert(db::get_paper_tags(conn, paper_id).await?);
+            let path = library.resolve(&row.get::<String>(1)?);
+            let name = Path::new(&path)
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+                .to_string();
+            paper_names.insert(paper_id, format!("{} (ID: {})", name, paper_id));
+        }
+
+        let entry = by_paper.entry(paper_id).or_default();
+        entry.duration += duration;
+        entry.sessions += 1;
+        let tags = &paper_tags[&paper_id];
+        if tags.is_empty() {
+            add_session(&mut by_tag, "(untagged)".to_string(), duration);
+        }
+        for tag in tags {
+            add_session(&mut by_tag, tag.clone(), duration);
+        }
+        total.duration += duration;
+        total.sessions += 1;
+    }
+
+    println!("Reading time from {} to {}", from, to);
+    if total.sessions > 0 {
+        let by_paper = by_paper
+            .into_iter()
+            .map(|(id, total)| (paper_names.remove(&id).unwrap_or_default(), total))
+            .collect();
+        print_totals("By paper", by_paper);
+        print_totals("By tag", by_tag);
+        println!(
+            "\nTotal: {} in {} session(s)",
+            format_duration(total.duration),
+            total.sessions
+        );
+    } else if open == 0 {
+        println!("\nNo sessions recorded. Sessions are recorded while `papr notes` runs.");
+    }
+    if open > 0 {
+        println!(
+            "\nNot counted: {} session(s) still running or never ended, e.g. because papr was killed.",
+            open
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::memory_db;
+
+    #[test]
+    fn formats_minutes_and_hours() {
+        assert_eq!(format_duration(Duration::seconds(59)), "0m");
+        assert_eq!(format_duration(Duration::minutes(12)), "12m");
+        assert_eq!(format_duration(Duration::minutes(65)), "1h 05m");
+    }
+
+    #[tokio::test]
+    async fn ends_only_the_given_session() {
+        let conn = memory_db().await;
+        conn.execute(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+             VALUES ('a', '', '2024-01-01', '')",
+            (),
+        )
+        .await
+        .unwrap();
+        let stale = start_session(&conn, 1).await.unwrap();
+        let current = start_session(&conn, 1).await.unwrap();
+
+        let duration = end_session(&conn, current).await.unwrap();
+        assert!(duration >= Duration::zero() && duration < Duration::minutes(1));
+
+        let mut rows = conn
+            .query("SELECT id FROM sessions WHERE ended_at IS NULL", ())
+            .await
+            .unwrap();
+        let row = rows.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<i64>(0).unwrap(), stale);
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate};
+use clap::ValueEnum;
+use inquire::Select;
+use std::fmt;
+
+use crate::library::Library;
+use crate::rating::stars;
+use crate::search::{self, FilteredPaper, PaperFilter};
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReadingStatus {
+    Inbox,
+    ToRead,
+    Reading,
+    Read,
+    Abandoned,
+}
+
+impl ReadingStatus {
+    const ALL: [Self; 5] = [
+        Self::Inbox,
+        Self::ToRead,
+        Self::Reading,
+        Self::Read,
+        Self::Abandoned,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Inbox => "inbox",
+            Self::ToRead => "to-read",
+            Self::Reading => "reading",
+            Self::Read => "read",
+            Self::Abandoned => "abandoned",
+        }
+    }
+}
+
+impl fmt::Display for ReadingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Change the status of a paper and record when it happened. Setting the
+/// status a paper already has records nothing.
+pub async fn set_status(
+    conn: &libsql::Connection,
+    paper_id: u32,
+    status: ReadingStatus,
+) -> Result<bool> {
+    let tx = conn.transaction().await?;
+    let changed = tx
+        .execute(
+            "UPDATE papers SET status = ?1 WHERE id = ?2 AND status != ?1",
+            (status.as_str(), paper_id),
+        )
+        .await
+        .context("Error updating paper status.")?;
+    if changed > 0 {
+        tx.execute(
+            "INSERT INTO status_changes (paper_id, status, changed_at) VALUES (?1, ?2, ?3)",
+            (
+                paper_id,
+                status.as_str(),
+                Local::now().format(TIMESTAMP_FORMAT).to_string(),
+            ),
+        )
+        .await
+        .context("Error recording status change.")?;
+    }
+    tx.commit().await?;
+    Ok(changed > 0)
+}
+
+async fn get_status(conn: &libsql::Connection, paper_id: u32) -> Result<(String, i64)> {
+    let mut rows = conn
+        .query(
+            "SELECT status, priority FROM papers WHERE id = ?1",
+            [paper_id],
+        )
+        .await?;
+    match rows.next().await? {
+        Some(row) => Ok((row.get(0)?, row.get(1)?)),
+        None => anyhow::bail!("Paper ID {} not found in database.", paper_id),
+    }
+}
+
+pub async fn handle_status(
+    conn: &libsql::Connection,
+    library: &Library,
+    query: String,
+    status: Option<ReadingStatus>,
+    priority: Option<i64>,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper:", matching_papers)
+        .prompt()
+        .context("No paper selected.")?;
+    let id = paper_selection.id;
+
+    let (current, current_priority) = get_status(conn, id).await?;
+
+    // Only ask when nothing was given on the command line
+    let status = match status {
+        Some(status) => Some(status),
+        None if priority.is_none() => {
+            let cursor = ReadingStatus::ALL
+                .iter()
+                .position(|s| s.as_str() == current)
+                .unwrap_or(0);
+            Some(
+                Select::new(
+                    &format!("Status (currently {}):", current),
+                    ReadingStatus::ALL.to_vec(),
+                )
+                .with_starting_cursor(cursor)
+                .prompt()?,
+            )
+        }
+        None => None,
+    };
+
+    if let Some(status) = status {
+        if set_status(conn, id, status).await? {
+            println!("Status changed from {} to {}.", current, status);
+        } else {
+            println!("Status is already {}.", status);
+        }
+    }
+
+    if let Some(priority) = priority
+        && priority != current_priority
+    {
+        conn.execute(
+            "UPDATE papers SET priority = ?1 WHERE id = ?2",
+            (priority, id),
+        )
+        .await
+        .context("Error updating paper priority.")?;
+        println!(
+            "Priority changed from {} to {}.",
+            current_priority, priority
+        );
+    }
+
+    let mut rows = conn
+        .query(
+            "SELECT status, changed_at FROM status_changes WHERE paper_id = ?1 ORDER BY id",
+            [id],
+        )
+        .await?;
+    let mut history = Vec::new();
+    while let Some(row) = rows.next().await? {
+        history.push(format!(
+            "  {}  {}",
+            row.get::<String>(1)?,
+            row.get::<String>(0)?
+        ));
+    }
+    if !history.is_empty() {
+        println!("\nStatus history:\n{}", history.join("\n"));
+    }
+
+    Ok(())
+}
+
+pub async fn handle_queue(
+    conn: &libsql::Connection,
+    library: &Library,
+    mut filter: PaperFilter,
+) -> Result<()> {
+    filter.status = Some(ReadingStatus::ToRead);
+    let mut papers = search::filter_papers(conn, library, &filter).await?;
+    // Highest priority first, then oldest first
+    papers.sort_by(|a, b| {
+        b.priority
+            .cmp(&a.priority)
+            .then(a.date_added.cmp(&b.date_added))
+            .then(a.id.cmp(&b.id))
+    });
+
+    if papers.is_empty() {
+        println!("Nothing to read. Mark papers with `papr status <query> to-read`.");
+        return Ok(());
+    }
+
+    let today = Local::now().date_naive();
+    for (i, paper) in papers.iter().enumerate() {
+        let age = NaiveDate::parse_from_str(&paper.date_added, "%Y-%m-%d")
+            .map(|date| format!(", {} days ago", (today - date).num_days()))
+            .unwrap_or_default();
+        println!(
+            "{}. [priority {}] {} (added {}{}){}",
+            i + 1,
+            paper.priority,
+            paper.canonical_base_path,
+            paper.date_added,
+            age,
+            rating_suffix(paper)
+        );
+    }
+    Ok(())
+}
+
+fn rating_suffix(paper: &FilteredPaper) -> String {
+    let mut suffix = String::new();
+    if let Some(rating) = paper.rating {
+        suffix.push_str(&format!(" {}", stars(rating)));
+    }
+    if paper.starred {
+        suffix.push_str(" (starred)");
+    }
+    suffix
+}
+
+pub async fn handle_list(
+    conn: &libsql::Connection,
+    library: &Library,
+    filter: PaperFilter,
+) -> Result<()> {
+    let papers = search::filter_papers(conn, library, &filter).await?;
+    if papers.is_empty() {
+        println!("No papers found.");
+        return Ok(());
+    }
+
+    for paper in &papers {
+        println!(
+            "{} [{}, priority {}] (ID: {}){}",
+            paper.canonical_base_path,
+            paper.status,
+            paper.priority,
+            paper.id,
+            rating_suffix(paper)
+        );
+    }
+    println!("\n{} paper(s).", papers.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::memory_db;
+
+    #[tokio::test]
+    async fn records_only_real_status_changes() {
+        let conn = memory_db().await;
+        conn.execute(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+             VALUES ('a', '', '2024-01-01', '')",
+            (),
+        )
+        .await
+        .unwrap();
+
+        assert!(set_status(&conn, 1, ReadingStatus::Reading).await.unwrap());
+        assert!(!set_status(&conn, 1, ReadingStatus::Reading).await.unwrap());
+        assert!(set_status(&conn, 1, ReadingStatus::Read).await.unwrap());
+        assert!(!set_status(&conn, 2, ReadingStatus::Read).await.unwrap());
+
+        assert_eq!(get_status(&conn, 1).await.unwrap().0, "read");
+        let mut rows = conn
+            .query("SELECT status FROM status_changes ORDER BY id", ())
+            .await
+            .unwrap();
+        let mut changes = Vec::new();
+        while let Some(row) = rows.next().await.unwrap() {
+            changes.push(row.get::<String>(0).unwrap());
+        }
+        assert_eq!(changes, ["reading", "read"]);
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use regex::Regex;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use crate::config::{TemplateConfig, get_config_dir};
+use crate::naming::NameParts;
+
+/// Name of the template that ships with papr.
+const BUILTIN_TEMPLATE: &str = "review";
+
+/// Structured review notes. Values are set once at the top, preferring the
+/// current metadata that `papr notes` passes in through `sys.inputs` over the
+/// values filled in when the paper was added.
+const REVIEW_TEMPLATE: &str = r#"#let meta(key, added) = sys.inputs.at(key, default: added)
+#let title = meta("title", "{{title}}")
+#let authors = meta("authors", "{{authors}}")
+#let year = meta("year", "{{year}}")
+#let url = meta("url", "{{url}}")
+#let key = meta("citation_key", "{{citation_key}}")
+#let tags = meta("tags", "{{tags}}")
+#let date_added = meta("date_added", "{{date_added}}")
+#let citation = meta("citation", "")
+
+#set text(font: "New Computer Modern")
+#show heading: it => [#it #v(0.2em)]
+
+#text(size: 2em)[#if url != "" { link(url)[#title] } else { title }]
+
+#if authors != "" [#authors ]#if year != "" [(#year)]
+
+#text(fill: gray)[#key, added #date_added#if tags != "" [, tagged #tags]]
+
+#if citation != "" [#text(size: 0.9em)[#citation]]
+
+= Summary
+
+= Contributions
+
+= Weaknesses
+
+= Questions
+"#;
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{(\w+)\}\}").unwrap());
+
+/// What a new note can be filled in with.
+pub struct NoteValues<'a> {
+    pub parts: &'a NameParts,
+    pub url: &'a str,
+    pub tag_names: &'a [String],
+}
+
+fn templates_dir() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join("templates"))
+}
+
+/// Pick a template name: the one given on the command line, then the first
+/// tag with a template, then the configured default.
+pub fn choose_template(
+    config: &TemplateConfig,
+    requested: Option<String>,
+    tag_names: &[String],
+) -> String {
+    requested
+        .or_else(|| {
+            tag_names.iter().find_map(|tag| {
+                config
+                    .tags
+                    .iter()
+                    .find(|(t, _)| t.eq_ignore_ascii_case(tag))
+                    .map(|(_, template)| template.clone())
+            })
+        })
+        .or_else(|| config.default.clone())
+        .unwrap_or_else(|| BUILTIN_TEMPLATE.to_string())
+}
+
+/// Read `<config dir>/templates/<name>.typ`. The built-in template is used
+/// when there is no file called `review.typ`.
+pub fn load_template(name: &str) -> Result<String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        anyhow::bail!("Invalid template name '{}'.", name);
+    }
+    let path = templates_dir()?.join(format!("{}.typ", name));
+    if path.exists() {
+        return fs::read_to_string(&path)
+            .with_context(|| format!("Error reading template {:?}.", path));
+    }
+    if name == BUILTIN_TEMPLATE {
+        return Ok(REVIEW_TEMPLATE.to_string());
+    }
+    anyhow::bail!("Template '{}' not found, expected {:?}.", name, path)
+}
+
+/// Escape text for use inside a Typst string literal.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Fill in `{{placeholder}}`s. Values are escaped for Typst strings, so
+/// templates should use them between double quotes. Anything that is not a
+/// known placeholder is left alone.
+pub fn render(template: &str, values: &NoteValues) -> String {
+    PLACEHOLDER
+        .replace_all(template, |caps: &regex::Captures| {
+            let value = match &caps[1] {
+                "title" => values.parts.title.clone(),
+                "authors" => values.parts.authors.clone().unwrap_or_default(),
+                "year" => values.parts.year.clone().unwrap_or_default(),
+                "url" => values.url.to_string(),
+                "citation_key" => values.parts.citation_key(),
+                "tags" => values.tag_names.join(", "),
+                "date_added" => Local::now().format("%Y-%m-%d").to_string(),
+                _ => return caps[0].to_string(),
+            };
+            escape(&value)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn escapes_typst_strings() {
+        assert_eq!(
+            escape("A \"quoted\" C:\\path\r\nnext"),
+            "A \\\"quoted\\\" C:\\\\path\\nnext"
+        );
+    }
+
+    #[test]
+    fn renders_known_placeholders_only() {
+        let parts = NameParts {
+            title: "Attention \"Is\" All".to_string(),
+            authors: Some("Ashish Vaswani".to_string()),
+            year: None,
+        };
+        let tags = ["nlp".to_string(), "ml".to_string()];
+        let values = NoteValues {
+            parts: &parts,
+            url: "",
+            tag_names: &tags,
+        };
+        assert_eq!(
+            render(
+                "{{title}}|{{authors}}|{{year}}|{{url}}|{{tags}}|{{citation_key}}|{{other}}",
+                &values
+            ),
+            "Attention \\\"Is\\\" All|Ashish Vaswani|||nlp, ml|vaswaniattention|{{other}}"
+        );
+    }
+
+    #[test]
+    fn prefers_requested_then_tag_then_default_template() {
+        let mut config = TemplateConfig {
+            default: Some("plain".to_string()),
+            tags: HashMap::from([("Math".to_string(), "proofs".to_string())]),
+        };
+        let tags = ["ml".to_string(), "math".to_string()];
+        assert_eq!(
+            choose_template(&config, Some("mine".to_string()), &tags),
+            "mine"
+        );
+        assert_eq!(choose_template(&config, None, &tags), "proofs");
+        assert_eq!(choose_template(&config, None, &[]), "plain");
+        config.default = None;
+        assert_eq!(choose_template(&config, None, &[]), BUILTIN_TEMPLATE);
+    }
+
+    #[test]
+    fn rejects_template_names_outside_the_templates_directory() {
+        for name in ["", "../secret", "a/b", ".hidden"] {
+            assert!(load_template(name).is_err());
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::{Context, Result};
+use chrono::{Duration, Local};
+use inquire::{Confirm, MultiSelect};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::delete_paper_links;
+use crate::fsutil::move_dir;
+use crate::library::Library;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug)]
+pub struct TrashedPaper {
+    pub id: u32,
+    pub canonical_base_path: String,
+    pub trash_path: String,
+    pub deleted_at: String,
+}
+
+impl fmt::Display for TrashedPaper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Path: {}\nDeleted at: {}\nID: {}",
+            self.canonical_base_path, self.deleted_at, self.id
+        )
+    }
+}
+
+/// Where the directory of paper `id` goes in the trash.
+pub(crate) fn trash_path(trash_dir: &Path, id: u32, canonical_base_path: &str) -> Result<PathBuf> {
+    let dir_name = Path::new(canonical_base_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("paper");
+    fs::create_dir_all(trash_dir).context("Error creating trash directory.")?;
+    Ok(fs::canonicalize(trash_dir)?.join(format!("{}_{}", id, dir_name)))
+}
+
+/// Mark a paper as deleted, with its directory at `trash_path`.
+pub(crate) async fn mark_trashed(
+    conn: &libsql::Connection,
+    library: &Library,
+    id: u32,
+    trash_path: &Path,
+) -> Result<()> {
+    conn.execute(
+        "UPDATE papers SET deleted_at = ?1, trash_path = ?2 WHERE id = ?3",
+        (
+            Local::now().format(TIMESTAMP_FORMAT).to_string(),
+            library.to_stored(trash_path)?,
+            id,
+        ),
+    )
+    .await
+    .context("Error marking paper as deleted.")?;
+    Ok(())
+}
+
+/// Move the directory of a paper already marked as deleted into the trash.
+/// If that fails the paper is unmarked again, so it stays where the DB says.
+pub(crate) async fn move_to_trash(
+    conn: &libsql::Connection,
+    id: u32,
+    canonical_base_path: &str,
+    trash_path: &Path,
+) -> Result<()> {
+    let base_path = Path::new(canonical_base_path);
+    if !base_path.exists() {
+        return Ok(());
+    }
+    if let Err(e) = move_dir(base_path, trash_path) {
+        conn.execute(
+            "UPDATE papers SET deleted_at = NULL, trash_path = NULL WHERE id = ?1",
+            [id],
+        )
+        .await?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Move a paper's directory into the trash and mark its row as deleted.
+/// The row and its tag links are kept so the paper can be restored later.
+pub async fn trash_paper(
+    conn: &libsql::Connection,
+    library: &Library,
+    trash_dir: &Path,
+    id: u32,
+    canonical_base_path: &str,
+) -> Result<()> {
+    let trash_path = trash_path(trash_dir, id, canonical_base_path)?;
+
+    let base_path = Path::new(canonical_base_path);
+    let moved = base_path.exists();
+    if moved {
+        move_dir(base_path, &trash_path)?;
+    }
+
+    if let Err(e) = mark_trashed(conn, library, id, &trash_path).await {
+        // Put the directory back where the DB still says it is
+        if moved {
+            move_dir(&trash_path, base_path)?;
+        }
+        return Err(e);
+    }
+
+    println!("Moved '{}' to the trash.", canonical_base_path);
+    Ok(())
+}
+
+async fn get_trashed_papers(
+    conn: &libsql::Connection,
+    library: &Library,
+    older_than: Option<String>,
+) -> Result<Vec<TrashedPaper>> {
+    let mut rows = match older_than {
+        Some(cutoff) => {
+            conn.query(
+                "SELECT id, canonical_base_path, trash_path, deleted_at FROM papers
+                 WHERE deleted_at IS NOT NULL AND deleted_at < ?1
+                 ORDER BY deleted_at",
+                [cutoff],
+            )
+            .await?
+        }
+        None => {
+            conn.query(
+                "SELECT id, canonical_base_path, trash_path, deleted_at FROM papers
+                 WHERE deleted_at IS NOT NULL
+                 ORDER BY deleted_at",
+                (),
+            )
+            .await?
+        }
+    };
+
+    let mut res = Vec::new();
+    while let Some(row) = rows.next().await? {
+        res.push(TrashedPaper {
+            id: row.get(0)?,
+            canonical_base_path: library.resolve_string(&row.get::<String>(1)?),
+            trash_path: row
+                .get::<Option<String>>(2)?
+                .map(|p| library.resolve_string(&p))
+                .unwrap_or_default(),
+            deleted_at: row.get(3)?,
+        });
+    }
+
+    Ok(res)
+}
+
+/// Permanently delete trashed papers, both their rows and their directories.
+async fn purge(conn: &libsql::Connection, papers: &[TrashedPaper]) -> Result<()> {
+    for paper in papers {
+        // A directory that fails to delete keeps its row, so it can be retried
+        let trash_path = Path::new(&paper.trash_path);
+        if !paper.trash_path.is_empty() && trash_path.exists() {
+            fs::remove_dir_all(trash_path)
+                .with_context(|| format!("Error deleting {:?}.", trash_path))?;
+        }
+
+        let tx = conn.transaction().await?;
+        delete_paper_links(&tx, paper.id).await?;
+        tx.execute("DELETE FROM papers WHERE id = ?1", [paper.id])
+            .await?;
+        tx.commit().await?;
+    }
+
+    // Prune orphan tags that no longer belong to any paper
+    conn.execute(
+        "DELETE FROM tags WHERE id NOT IN (SELECT DISTINCT tag_id FROM paper_tags)",
+        (),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Purge papers that have been in the trash for longer than the retention period.
+pub async fn purge_expired_trash(
+    conn: &libsql::Connection,
+    library: &Library,
+    retention_days: u32,
+) -> Result<()> {
+    if retention_days == 0 {
+        return Ok(());
+    }
+
+    let cutoff = (Local::now() - Duration::days(retention_days.into()))
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+    let expired = get_trashed_papers(conn, library, Some(cutoff)).await?;
+    if !expired.is_empty() {
+        println!(
+            "Purging {} paper(s) trashed more than {} days ago.",
+            expired.len(),
+            retention_days
+        );
+        purge(conn, &expired).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn handle_trash_list(conn: &libsql::Connection, library: &Library) -> Result<()> {
+    let trashed = get_trashed_papers(conn, library, None).await?;
+    if trashed.is_empty() {
+        println!("Trash is empty.");
+        return Ok(());
+    }
+
+    for paper in trashed {
+        println!("{}\nTrash path: {}\n", paper, paper.trash_path);
+    }
+
+    Ok(())
+}
+
+pub async fn handle_trash_restore(conn: &libsql::Connection, library: &Library) -> Result<()> {
+    let trashed = get_trashed_papers(conn, library, None).await?;
+    if trashed.is_empty() {
+        anyhow::bail!("Trash is empty.");
+    }
+
+    let selections = MultiSelect::new(
+        "Select papers to restore (Space to toggle, Enter to confirm):",
+        trashed,
+    )
+    .prompt()
+    .context("No papers selected for restoring.")?;
+
+    for paper in selections {
+        let original_path = Path::new(&paper.canonical_base_path);
+        if original_path.exists() {
+            println!(
+                "Cannot restore '{}': the path already exists.",
+                paper.canonical_base_path
+            );
+            continue;
+        }
+
+        let trash_path = Path::new(&paper.trash_path);
+        let moved = trash_path.exists();
+        if moved {
+            move_dir(trash_path, original_path)?;
+        } else {
+            println!(
+                "Warning: trashed directory {:?} is missing, restoring the DB entry only.",
+                trash_path
+            );
+        }
+
+        let res = conn
+            .execute(
+                "UPDATE papers SET deleted_at = NULL, trash_path = NULL WHERE id = ?1",
+                [paper.id],
+            )
+            .await;
+        if let Err(e) = res {
+            if moved {
+                move_dir(original_path, trash_path)?;
+            }
+            return Err(e).context("Error restoring paper.");
+        }
+
+        println!("Restored '{}'.", paper.canonical_base_path);
+    }
+
+    Ok(())
+}
+
+pub async fn handle_trash_empty(conn: &libsql::Connection, library: &Library) -> Result<()> {
+    let trashed = get_trashed_papers(conn, library, None).await?;
+    if trashed.is_empty() {
+        println!("Trash is empty.");
+        return Ok(());
+    }
+
+    let ans = Confirm::new(&format!(
+        "Permanently delete {} paper(s) in the trash?",
+        trashed.len()
+    ))
+    .with_default(false)
+    .with_help_message("This removes the papers and their notes for good.")
+    .prompt()?;
+
+    if !ans {
+        println!("Empty operation cancelled.");
+        return Ok(());
+    }
+
+    purge(conn, &trashed).await?;
+    println!("Trash emptied.");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::tests::memory_db;
+
+    #[tokio::test]
+    async fn trashes_and_purges_papers() {
+        let conn = memory_db().await;
+        let root = tempfile::tempdir().unwrap();
+        let library = Library::open(
+            &conn,
+            &root.path().join("papr.db"),
+            false,
+            &Config::default(),
+        )
+        .await
+        .unwrap();
+        let paper = library.root().join("paper");
+        fs::create_dir_all(paper.join("summary")).unwrap();
+        conn.execute(
+            "INSERT INTO papers (canonical_base_path, url, date_added, citation)
+             VALUES ('paper', '', '2024-01-01', '')",
+            (),
+        )
+        .await
+        .unwrap();
+
+        let trash_dir = root.path().join(".trash");
+        trash_paper(&conn, &library, &trash_dir, 1, &paper.to_string_lossy())
+            .await
+            .unwrap();
+        assert!(!paper.exists());
+        let trashed = get_trashed_papers(&conn, &library, None).await.unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert!(Path::new(&trashed[0].trash_path).join("summary").is_dir());
+
+        purge(&conn, &trashed).await.unwrap();
+        assert!(!Path::new(&trashed[0].trash_path).exists());
+        let mut rows = conn.query("SELECT COUNT(*) FROM papers", ()).await.unwrap();
+        let count: u32 = rows.next().await.unwrap().unwrap().get(0).unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,362 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::SystemTime;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::db;
+use crate::library::Library;
+use crate::links;
+use crate::naming::NameParts;
+use crate::search::{self, PaperFilter};
+
+/// A diagnostic in `--diagnostic-format short`: `file:line:column: severity: message`.
+static DIAGNOSTIC: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(.+?):(\d+):\d+: (error|warning): (.*)$").unwrap());
+
+/// Current metadata of a paper, readable in notes as `sys.inputs.<key>`.
+/// Every value is a string. Unknown values are left out, so notes fall back
+/// to what they were filled in with.
+pub async fn paper_inputs(
+    conn: &libsql::Connection,
+    library: &Library,
+    paper_id: u32,
+) -> Result<Vec<(&'static str, String)>> {
+    let mut rows = conn
+        .query(
+            "SELECT canonical_base_path, url, date_added, citation, title, status, rating,
+                    authors, year
+             FROM papers WHERE id = ?1",
+            [paper_id],
+        )
+        .await?;
+    let Some(row) = rows.next().await? else {
+        anyhow::bail!("Paper ID {} not found in database.", paper_id);
+    };
+    let path = library.resolve(&row.get::<String>(0)?);
+    let citation: String = row.get(3)?;
+
+    // Papers added before titles were stored fall back to their directory name
+    let title = row.get::<Option<String>>(4)?.unwrap_or_else(|| {
+        Path::new(&path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .replace('_', " ")
+    });
+    // Same as when the notes were filled in, so the citation key stays put
+    let mut parts = NameParts {
+        title,
+        authors: row.get(7)?,
+        year: row.get(8)?,
+    };
+    parts.fill_from_citation(&citation);
+
+    let inputs = vec![
+        ("citation_key", parts.citation_key()),
+        ("title", parts.title),
+        ("authors", parts.authors.unwrap_or_default()),
+        ("year", parts.year.unwrap_or_default()),
+        ("url", row.get(1)?),
+        ("citation", citation),
+        ("tags", db::get_paper_tags(conn, paper_id).await?.join(", ")),
+        ("date_added", row.get(2)?),
+        ("status", row.get(5)?),
+        (
+            "rating",
+            row.get::<Option<u32>>(6)?
+                .map(|r| r.to_string())
+                .unwrap_or_default(),
+        ),
+    ];
+    Ok(inputs
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect())
+}
+
+/// Arguments for `typst compile` or `typst watch` with `inputs` passed as
+/// `--input key=value`.
+pub fn typst_args(
+    subcommand: &str,
+    file: &Path,
+    output: &Path,
+    inputs: &[(&str, String)],
+) -> Vec<OsString> {
+    let mut args = vec![OsString::from(subcommand)];
+    for (key, value) in inputs {
+        args.push("--input".into());
+        args.push(format!("{}={}", key, value).into());
+    }
+    args.push(file.into());
+    args.push(output.into());
+    args
+}
+
+/// Notes of one paper waiting to be compiled.
+struct BuildJob {
+    name: String,
+    /// The wrapper from `links::write_wrapper`, next to the notes
+    typst_file: PathBuf,
+    output_pdf: PathBuf,
+    inputs: Vec<(&'static str, String)>,
+}
+
+/// Where the hash of the inputs an output was built with is kept, e.g.
+/// `main.pdf.inputs`.
+fn inputs_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".inputs");
+    PathBuf::from(path)
+}
+
+fn hash_inputs(inputs: &[(&str, String)]) -> String {
+    let mut hasher = Sha256::new();
+    for (key, value) in inputs {
+        hasher.update(format!("{}={}\n", key, value));
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Modification time of the newest file under `dir`, ignoring the files in
+/// `built`.
+fn newest_source(dir: &Path, built: &[&Path]) -> Result<Option<SystemTime>> {
+    let mut newest = None;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let modified = if path.is_dir() {
+            newest_source(&path, built)?
+        } else if built.contains(&path.as_path()) {
+            None
+        } else {
+            Some(fs::metadata(&path)?.modified()?)
+        };
+        newest = newest.max(modified);
+    }
+    Ok(newest)
+}
+
+/// Whether `output` was built from the current `inputs` after every other
+/// file in `summary_dir` was last changed.
+fn is_up_to_date(summary_dir: &Path, output: &Path, inputs: &[(&str, String)]) -> bool {
+    let Ok(built) = fs::metadata(output).and_then(|m| m.modified()) else {
+        return false;
+    };
+    let inputs_path = inputs_path(output);
+    if fs::read_to_string(&inputs_path).ok() != Some(hash_inputs(inputs)) {
+        return false;
+    }
+    matches!(
+        newest_source(summary_dir, &[output, &inputs_path]),
+        Ok(Some(source)) if source <= built
+    )
+}
+
+/// Compile one paper's notes. Returns the errors reported by Typst, which
+/// are empty on success.
+async fn compile(job: &BuildJob) -> Result<Vec<String>> {
+    let output = tokio::process::Command::new("typst")
+        .args(typst_args(
+            "compile",
+            &job.typst_file,
+            &job.output_pdf,
+            &job.inputs,
+        ))
+        .args(["--diagnostic-format", "short"])
+        .output()
+        .await
+        .context("Failed to start 'typst compile'. Is it installed?")?;
+    if output.status.success() {
+        fs::write(inputs_path(&job.output_pdf), hash_inputs(&job.inputs))
+            .context("Error recording the inputs of the build.")?;
+        return Ok(Vec::new());
+    }
+
+    // Typst reports paths relative to the notes directory
+    let root = job.typst_file.parent().unwrap_or(Path::new("."));
+    let mut errors = parse_errors(root, &String::from_utf8_lossy(&output.stderr));
+    if errors.is_empty() {
+        errors.push(format!("typst exited with {}", output.status));
+    }
+    Ok(errors)
+}
+
+/// The errors in Typst's short diagnostics, with paths made relative to
+/// `root`. Output in any other format is returned line by line.
+fn parse_errors(root: &Path, stderr: &str) -> Vec<String> {
+    let errors = stderr
+        .lines()
+        .filter_map(|line| DIAGNOSTIC.captures(line))
+        .filter(|caps| &caps[3] == "error")
+        .map(|caps| {
+            format!(
+                "{}:{}: error: {}",
+                root.join(&caps[1]).display(),
+                &caps[2],
+                &caps[4]
+            )
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return errors;
+    }
+    stderr
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub async fn handle_notes_build(
+    conn: &libsql::Connection,
+    library: &Library,
+    tags: Option<Vec<String>>,
+    force: bool,
+) -> Result<()> {
+    let filter = PaperFilter {
+        tags,
+        ..Default::default()
+    };
+    let papers = search::filter_papers(conn, library, &filter).await?;
+
+    let mut jobs = Vec::new();
+    let mut up_to_date = 0;
+    for paper in papers {
+        let base_path = Path::new(&paper.canonical_base_path);
+        links::index_paper(conn, paper.id, base_path).await?;
+        let summary_dir = base_path.join("summary");
+        // Papers without notes have nothing to build
+        let Ok(typst_file) = crate::find_typst_file(&summary_dir) else {
+            continue;
+        };
+        let output_pdf = typst_file.with_extension("pdf");
+        let inputs = paper_inputs(conn, library, paper.id).await?;
+        if !force && is_up_to_date(&summary_dir, &output_pdf, &inputs) {
+            up_to_date += 1;
+            continue;
+        }
+        jobs.push(BuildJob {
+            name: base_path
+                .file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+                .to_string(),
+            typst_file: links::write_wrapper(&typst_file)?,
+            output_pdf,
+            inputs,
+        });
+    }
+
+    if jobs.is_empty() {
+        println!("Nothing to build, {} paper(s) up to date.", up_to_date);
+        return Ok(());
+    }
+    println!("Building notes for {} paper(s)...", jobs.len());
+
+    // One Typst process per core at a time
+    let limit = std::thread::available_parallelism().map_or(4, |n| n.get());
+    let semaphore = Arc::new(Semaphore::new(limit));
+    let mut tasks = JoinSet::new();
+    for job in jobs {
+        let permit = semaphore.clone().acquire_owned().await?;
+        tasks.spawn(async move {
+            let result = compile(&job).await;
+            drop(permit);
+            (job.name, result)
+        });
+    }
+
+    let mut failures = Vec::new();
+    let mut built = 0;
+    while let Some(task) = tasks.join_next().await {
+        let (name, errors) = task?;
+        let errors = errors?;
+        if errors.is_empty() {
+            built += 1;
+        } else {
+            failures.push((name, errors));
+        }
+    }
+
+    failures.sort();
+    for (name, errors) in &failures {
+        println!("\n{}:", name);
+        for error in errors {
+            println!("  {}", error);
+        }
+    }
+    println!(
+        "\nBuilt {}, up to date {}, failed {}.",
+        built,
+        up_to_date,
+        failures.len()
+    );
+    if !failures.is_empty() {
+        anyhow::bail!("{} paper(s) failed to build.", failures.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(title: &str) -> Vec<(&'static str, String)> {
+        vec![("title", title.to_string()), ("year", "2017".to_string())]
+    }
+
+    #[test]
+    fn keeps_only_errors_from_diagnostics() {
+        let stderr = "main.typ:3:5: warning: unused variable\n\
+            main.typ:12:1: error: unknown variable: foo\n\
+            sections/a.typ:2:10: error: expected expression\n";
+        assert_eq!(
+            parse_errors(Path::new("/notes"), stderr),
+            vec![
+                "/notes/main.typ:12: error: unknown variable: foo",
+                "/notes/sections/a.typ:2: error: expected expression",
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_raw_output() {
+        let stderr = "error: file not found\n\n  searched at main.typ\n";
+        assert_eq!(
+            parse_errors(Path::new("/notes"), stderr),
+            vec!["error: file not found", "  searched at main.typ"]
+        );
+    }
+
+    #[test]
+    fn rebuilds_when_notes_or_inputs_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("main.typ");
+        let output = dir.path().join("main.pdf");
+        fs::write(&notes, "= Notes").unwrap();
+        assert!(!is_up_to_date(dir.path(), &output, &inputs("A")));
+
+        fs::write(&output, "%PDF").unwrap();
+        fs::write(inputs_path(&output), hash_inputs(&inputs("A"))).unwrap();
+        assert!(is_up_to_date(dir.path(), &output, &inputs("A")));
+        assert!(!is_up_to_date(dir.path(), &output, &inputs("B")));
+
+        let modified = fs::metadata(&output).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&notes)
+            .unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert!(!is_up_to_date(dir.path(), &output, &inputs("A")));
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{Context, Result};
+use inquire::Select;
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::ViewerConfig;
+use crate::library::Library;
+use crate::search;
+
+/// Split a command template and fill in its `{placeholder}`s.
+fn expand_command(template: &str, values: &[(&str, &str)]) -> Result<Vec<String>> {
+    let args = shlex::split(template)
+        .filter(|args| !args.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Invalid command '{}'.", template))?;
+    Ok(args
+        .into_iter()
+        .map(|arg| {
+            values
+                .iter()
+                .fold(arg, |arg, (key, value)| arg.replace(key, value))
+        })
+        .collect())
+}
+
+/// Open a PDF with the configured viewer command, or the system viewer if
+/// there is none. The system viewer cannot be told which page to show.
+pub fn open_pdf(config: &ViewerConfig, file: &Path, page: Option<usize>) -> Result<()> {
+    let Some(template) = &config.command else {
+        open::that(file).with_context(|| format!("Error opening {}.", file.display()))?;
+        if let Some(page) = page {
+            println!("Go to page {} in the viewer.", page);
+        }
+        return Ok(());
+    };
+
+    let args = expand_command(
+        template,
+        &[
+            ("{page}", &page.unwrap_or(1).to_string()),
+            ("{file}", &file.to_string_lossy()),
+        ],
+    )?;
+
+    // The viewer outlives papr, so it is not waited for
+    Command::new(&args[0])
+        .args(&args[1..])
+        .spawn()
+        .with_context(|| format!("Failed to start viewer '{}'.", args[0]))?;
+    Ok(())
+}
+
+/// Open a note at `line` with the configured editor command, falling back to
+/// `$VISUAL` or `$EDITOR` and then the system default.
+pub fn open_note(config: &ViewerConfig, file: &Path, line: usize) -> Result<()> {
+    let template = config.editor.clone().or_else(|| {
+        std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .ok()
+            .filter(|editor| !editor.trim().is_empty())
+            .map(|editor| format!("{} +{{line}} {{file}}", editor))
+    });
+    let Some(template) = template else {
+        open::that(file).with_context(|| format!("Error opening {}.", file.display()))?;
+        println!("Go to line {} in the editor.", line);
+        return Ok(());
+    };
+
+    let args = expand_command(
+        &template,
+        &[
+            ("{line}", &line.to_string()),
+            ("{file}", &file.to_string_lossy()),
+        ],
+    )?;
+
+    // Terminal editors need the terminal until they exit
+    let status = Command::new(&args[0])
+        .args(&args[1..])
+        .status()
+        .with_context(|| format!("Failed to start editor '{}'.", args[0]))?;
+    if !status.success() {
+        anyhow::bail!("Editor '{}' exited with an error.", args[0]);
+    }
+    Ok(())
+}
+
+pub async fn handle_open(
+    conn: &libsql::Connection,
+    library: &Library,
+    config: &ViewerConfig,
+    query: String,
+    page: Option<usize>,
+) -> Result<()> {
+    let matching_papers = search::fuzzy_search_papers(conn, library, &query).await?;
+    if matching_papers.is_empty() {
+        anyhow::bail!("No papers found matching '{}'", query);
+    }
+    let paper_selection = Select::new("Select paper to open:", matching_papers)
+        .prompt()
+        .context("No paper selected.")?;
+
+    let pdf_path = Path::new(&paper_selection.canonical_base_path).join("paper.pdf");
+    if !pdf_path.exists() {
+        anyhow::bail!(
+            "{} does not exist. Run `papr refetch` to download it again.",
+            pdf_path.display()
+        );
+    }
+    open_pdf(config, &pdf_path, page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_placeholders_after_splitting() {
+        assert_eq!(
+            expand_command(
+                "zathura --page={page} '{file}'",
+                &[("{page}", "7"), ("{file}", "/my papers/paper.pdf")]
+            )
+            .unwrap(),
+            ["zathura", "--page=7", "/my papers/paper.pdf"]
+        );
+        // A path with spaces stays one argument even without quotes
+        assert_eq!(
+            expand_command(
+                "vim +{line} {file}",
+                &[("{line}", "3"), ("{file}", "/a b.typ")]
+            )
+            .unwrap(),
+            ["vim", "+3", "/a b.typ"]
+        );
+    }
+
+    #[test]
+    fn rejects_empty_or_unbalanced_commands() {
+        assert!(expand_command("", &[]).is_err());
+        assert!(expand_command("  ", &[]).is_err());
+        assert!(expand_command("open 'unterminated", &[]).is_err());
+    }
+}